@@ -0,0 +1,144 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{Receiver, RecvError};
+
+// The future returned by Receiver::recv_async.
+pub struct RecvFuture<'a, T> {
+    receiver: &'a Receiver<T>,
+    // Set once the future has registered a waker with the channel.
+    id: Option<usize>,
+}
+
+impl<'a, T> RecvFuture<'a, T> {
+    pub(crate) fn new(receiver: &'a Receiver<T>) -> Self {
+        Self { receiver, id: None }
+    }
+}
+
+impl<'a, T> Future for RecvFuture<'a, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &this.receiver.inner;
+        let mut que = inner.shared.lock().map_err(|_| RecvError::Poisoned)?;
+        if let Some(elem) = inner.pop(&mut que) {
+            return Poll::Ready(Ok(elem));
+        }
+        if inner.no_senders() {
+            return Poll::Ready(Err(RecvError::Disconnected));
+        }
+
+        // The waker is registered while we still hold the queue lock. Senders
+        // push under that lock before waking, so a value sent after our check
+        // is guaranteed to find our waker.
+        let id = *this.id.get_or_insert_with(|| inner.new_future_id());
+        inner.register_waker(id, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl<'a, T> Drop for RecvFuture<'a, T> {
+    fn drop(&mut self) {
+        // A future dropped while pending would otherwise leave its waker
+        // behind until the next value is sent.
+        if let Some(id) = self.id {
+            self.receiver.inner.unregister_waker(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use std::thread::{self, sleep, spawn, Thread};
+    use std::time::Duration;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // A minimal executor that parks the current thread until woken.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_recv_async_ready_value() {
+        let (sender, receiver) = channel();
+        sender.send(42).unwrap();
+
+        assert_eq!(block_on(receiver.recv_async()), Ok(42));
+    }
+
+    #[test]
+    fn test_recv_async_value_from_other_thread() {
+        let (sender, receiver) = channel();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            sender.send(42).unwrap();
+        });
+
+        assert_eq!(block_on(receiver.recv_async()), Ok(42));
+        assert!(receiver.inner.wakers.lock().unwrap().is_empty());
+        assert_eq!(receiver.inner.num_wakers.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_dropped_recv_future_unregisters() {
+        let (sender, receiver) = channel::<u32>();
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut first = Box::pin(receiver.recv_async());
+        let mut second = Box::pin(receiver.recv_async());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        // Polling again replaces the waker instead of adding another one
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(receiver.inner.wakers.lock().unwrap().len(), 2);
+
+        drop(first);
+        assert_eq!(receiver.inner.wakers.lock().unwrap().len(), 1);
+        assert_eq!(receiver.inner.num_wakers.load(Ordering::Relaxed), 1);
+
+        // The other future polled by the same task is still woken
+        sender.send(42).unwrap();
+        assert_eq!(receiver.inner.num_wakers.load(Ordering::Relaxed), 0);
+        assert_eq!(second.as_mut().poll(&mut cx), Poll::Ready(Ok(42)));
+    }
+
+    #[test]
+    fn test_recv_async_disconnected() {
+        let (sender, receiver) = channel::<u32>();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            drop(sender);
+        });
+
+        assert_eq!(
+            block_on(receiver.recv_async()),
+            Err(RecvError::Disconnected)
+        );
+    }
+}
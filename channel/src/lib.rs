@@ -1,266 +1,2198 @@
-use std::collections::VecDeque;
-use std::sync::Mutex;
-use std::sync::{Arc, Condvar};
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::PoisonError;
+use std::sync::{Arc, Weak};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+use futer::{Futer, FuterCondvar};
+
+mod future;
+mod lock;
+pub mod oneshot;
+mod queue;
+mod ring;
+mod select;
+
+pub use future::RecvFuture;
+use lock::{PoisonFuter, PoisonGuard};
+use queue::Queue;
+pub use select::Select;
+use select::Signal;
 
 struct Inner<T> {
-    shared: Mutex<VecDeque<T>>,
-    cvar: Condvar,
+    shared: PoisonFuter<Queue<T>>,
+    cvar: FuterCondvar,
+    // Signaled by the receiver whenever it pops a value, for senders blocked
+    // on a full bounded channel.
+    not_full: FuterCondvar,
+    // Signaled whenever a receiver takes the last queued value, for senders
+    // in wait_empty.
+    empty: FuterCondvar,
+    // None for an unbounded channel.
+    capacity: Option<usize>,
+    // How many values have ever been pushed to and popped from the queue.
+    // Only updated with the shared lock held. A rendezvous sender uses them
+    // as a handshake: its value has been taken once taken reaches the value
+    // sent had right after pushing it.
+    sent: AtomicUsize,
+    taken: AtomicUsize,
+    // The most values the queue has held at once.
+    high_water_mark: AtomicUsize,
+    // Live handles on each side. Arc::strong_count cannot tell senders and
+    // receivers apart once either side can be cloned, so they are counted
+    // explicitly to detect disconnection.
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    // Set by Sender::close. A closed channel refuses new values, and
+    // receivers treat it as disconnected once it has been drained.
+    closed: AtomicBool,
+    // Signals of the Selects currently waiting on this channel.
+    selectors: Futer<Vec<Arc<Signal>>>,
+    // Wakers of the RecvFutures currently pending on this channel, along
+    // with the id of the future that registered each.
+    wakers: Futer<Vec<(usize, Waker)>>,
+    // Hands out the ids of RecvFutures, see register_waker.
+    next_future_id: AtomicUsize,
+    // The lengths of selectors and wakers, so that pushing a value does not
+    // have to lock them when nobody waits, which is the common case. Only
+    // updated with the respective list locked.
+    num_selectors: AtomicUsize,
+    num_wakers: AtomicUsize,
+}
+
+impl<T> Inner<T> {
+    pub fn new() -> Self {
+        Self::with_queue(None, Queue::new(1))
+    }
+
+    pub fn bounded(capacity: usize) -> Self {
+        Self::with_queue(Some(capacity), Queue::bounded(capacity))
+    }
+
+    pub fn preallocated(capacity: usize) -> Self {
+        Self::with_queue(None, Queue::with_capacity(capacity))
+    }
+
+    pub fn prioritized(levels: usize) -> Self {
+        Self::with_queue(None, Queue::new(levels))
+    }
+
+    fn with_queue(capacity: Option<usize>, queue: Queue<T>) -> Self {
+        let shared = PoisonFuter::new(queue);
+        let cvar = FuterCondvar::new();
+        let not_full = FuterCondvar::new();
+        let empty = FuterCondvar::new();
+        let sent = AtomicUsize::new(0);
+        let taken = AtomicUsize::new(0);
+        let high_water_mark = AtomicUsize::new(0);
+        let senders = AtomicUsize::new(0);
+        let receivers = AtomicUsize::new(0);
+        let closed = AtomicBool::new(false);
+        let selectors = Futer::new(Vec::new());
+        let wakers = Futer::new(Vec::new());
+        let num_selectors = AtomicUsize::new(0);
+        let num_wakers = AtomicUsize::new(0);
+        let next_future_id = AtomicUsize::new(0);
+        Self {
+            shared,
+            cvar,
+            not_full,
+            empty,
+            capacity,
+            sent,
+            taken,
+            high_water_mark,
+            senders,
+            receivers,
+            closed,
+            selectors,
+            wakers,
+            next_future_id,
+            num_selectors,
+            num_wakers,
+        }
+    }
+
+    // True once every sender is gone or the channel was closed, so no more
+    // values will arrive.
+    fn no_senders(&self) -> bool {
+        self.senders.load(Ordering::Acquire) == 0 || self.is_closed()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    // True once every receiver is gone, so nobody will read sent values.
+    fn no_receivers(&self) -> bool {
+        self.receivers.load(Ordering::Acquire) == 0
+    }
+
+    fn register_selector(&self, signal: &Arc<Signal>) {
+        let mut selectors = self.selectors.lock().unwrap();
+        selectors.push(Arc::clone(signal));
+        self.num_selectors.store(selectors.len(), Ordering::Relaxed);
+    }
+
+    fn unregister_selector(&self, signal: &Arc<Signal>) {
+        let mut selectors = self.selectors.lock().unwrap();
+        selectors.retain(|registered| !Arc::ptr_eq(registered, signal));
+        self.num_selectors.store(selectors.len(), Ordering::Relaxed);
+    }
+
+    // Relaxed loads are enough for both counts: a Select or RecvFuture
+    // registers before it looks at the queue under the shared lock, and
+    // values are pushed under that same lock before notifying, so either it
+    // sees the value or the sender sees it registered.
+    fn notify_selectors(&self) {
+        if self.num_selectors.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        for signal in self.selectors.lock().unwrap().iter() {
+            signal.notify();
+        }
+    }
+
+    fn new_future_id(&self) -> usize {
+        self.next_future_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Registers the waker of the future with the given id, replacing the one
+    // it registered before, if it is still there. Ids rather than
+    // Waker::will_wake tell the entries apart, since several futures polled
+    // by the same task share a waker but unregister on their own.
+    fn register_waker(&self, id: usize, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        match wakers.iter_mut().find(|(registered, _)| *registered == id) {
+            Some((_, registered)) => registered.clone_from(waker),
+            None => wakers.push((id, waker.clone())),
+        }
+        self.num_wakers.store(wakers.len(), Ordering::Relaxed);
+    }
+
+    // Drops the waker of a future that is going away without being woken.
+    fn unregister_waker(&self, id: usize) {
+        if self.num_wakers.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut wakers = self.wakers.lock().unwrap();
+        wakers.retain(|(registered, _)| *registered != id);
+        self.num_wakers.store(wakers.len(), Ordering::Relaxed);
+    }
+
+    // True if some RecvFuture is registered, so wake_futures has work to do.
+    fn has_futures(&self) -> bool {
+        self.num_wakers.load(Ordering::Relaxed) != 0
+    }
+
+    // Wakes every pending RecvFuture. They re-register if they poll and
+    // still find nothing to receive.
+    //
+    // This does not need the shared lock: a future registers before
+    // releasing it, so it is on the list by the time whoever pushed a value
+    // or disconnected after its check gets here. The wakers run after the
+    // list is unlocked too, since they may take arbitrarily long.
+    fn wake_futures(&self) {
+        if !self.has_futures() {
+            return;
+        }
+        let wakers = {
+            let mut wakers = self.wakers.lock().unwrap();
+            self.num_wakers.store(0, Ordering::Relaxed);
+            mem::take(&mut *wakers)
+        };
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+    }
+
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Acquire)
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.receivers.load(Ordering::Acquire)
+    }
+
+    // The length is only a snapshot: other threads may send or receive as
+    // soon as the lock is released, so it can be stale by the time it is used.
+    fn len(&self) -> usize {
+        // Reading the length cannot be thrown off by a poisoned queue.
+        self.shared
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    // Pushes a value and wakes up whoever waits for one, except for the
+    // RecvFutures, which the caller wakes with wake_futures once it can
+    // release the lock. Returns the rendezvous ticket of the value, see sent.
+    fn push(&self, que: &mut Queue<T>, level: usize, t: T) -> usize {
+        que.push(level, t);
+        // Relaxed is enough, the mark is only a statistic.
+        self.high_water_mark.fetch_max(que.len(), Ordering::Relaxed);
+        let ticket = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        self.cvar.notify_one();
+        self.notify_selectors();
+        ticket
+    }
+
+    // Takes back the value a rendezvous sender pushed, which is the only one
+    // in the queue since it has not been taken yet.
+    fn withdraw(&self, que: &mut Queue<T>, level: usize) -> T {
+        // Nobody else can have pushed after us, so handing our ticket back
+        // keeps the next sender's ticket in step with taken.
+        self.sent.fetch_sub(1, Ordering::Relaxed);
+        que.pop_back(level).unwrap()
+    }
+
+    // Pops the next value, waking up any senders waiting for room.
+    fn pop(&self, que: &mut Queue<T>) -> Option<T> {
+        let elem = que.pop_front();
+        if elem.is_some() {
+            self.taken.fetch_add(1, Ordering::Relaxed);
+        }
+        if elem.is_some() && self.capacity.is_some() {
+            // Wake all senders: some wait for room in the queue, while a
+            // rendezvous sender waits for its value to be taken.
+            self.not_full.notify_all();
+        }
+        if elem.is_some() && que.is_empty() {
+            self.empty.notify_all();
+        }
+        elem
+    }
+}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    fn new(inner: Arc<Inner<T>>) -> Self {
+        inner.senders.fetch_add(1, Ordering::AcqRel);
+        Self { inner }
+    }
+
+    // Number of values waiting in the channel. This is a racy snapshot.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The maximum number of values the channel holds, or None if unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.inner.capacity
+    }
+
+    // Number of live senders and receivers of the channel. Like len, these
+    // are snapshots that other threads may change at any time.
+    pub fn sender_count(&self) -> usize {
+        self.inner.sender_count()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.inner.receiver_count()
+    }
+
+    // True once every receiver is gone or the channel was closed, so any
+    // send is going to fail. A false result may be stale by the time it is
+    // used, since receivers can be dropped at any time.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.no_receivers() || self.inner.is_closed()
+    }
+
+    // Creates a sender that does not keep the channel connected.
+    pub fn downgrade(&self) -> WeakSender<T> {
+        let inner = Arc::downgrade(&self.inner);
+        WeakSender { inner }
+    }
+
+    // Disconnects the channel without waiting for every sender to be dropped.
+    // Values already sent can still be received, after which recv returns
+    // Disconnected. Any send from now on fails, for every sender.
+    pub fn close(&self) {
+        // Same as dropping the last sender, the lock makes sure no receiver
+        // misses the wakeup.
+        let que = self.inner.shared.lock();
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.cvar.notify_all();
+        self.inner.not_full.notify_all();
+        self.inner.empty.notify_all();
+        self.inner.notify_selectors();
+        drop(que);
+        self.inner.wake_futures();
+    }
+
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.send_with_priority(t, 0)
+    }
+
+    // Sends a value at the given priority level. Receivers take values from
+    // the highest non-empty level first, and in order within a level. Plain
+    // send uses level 0, the lowest one.
+    //
+    // Panics if level is not below the levels of a priority_channel, or is
+    // not 0 for any other channel.
+    pub fn send_with_priority(&self, t: T, level: usize) -> Result<(), SendError<T>> {
+        self.send_deadline(t, level, None)
+            .map_err(|err| SendError(err.into_inner()))
+    }
+
+    // Like send, but gives up once timeout has elapsed without the channel
+    // having room for the value, or, for a rendezvous channel, without a
+    // receiver taking it. The value is handed back in either error.
+    pub fn send_timeout(&self, t: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        // A timeout too long to represent never expires.
+        let deadline = Instant::now().checked_add(timeout);
+        self.send_deadline(t, 0, deadline)
+    }
+
+    fn send_deadline(
+        &self,
+        t: T,
+        level: usize,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<T>> {
+        let (mut que, ticket) = self.push_deadline(t, level, deadline, |t| t)?;
+        if self.inner.capacity != Some(0) {
+            drop(que);
+            self.inner.wake_futures();
+            return Ok(());
+        }
+
+        // A rendezvous sender keeps the lock to wait for its value to be
+        // taken, so it only lets go of it if there are futures to wake.
+        if self.inner.has_futures() {
+            que.unlocked(|| self.inner.wake_futures());
+        }
+        // Only return once a receiver has taken our value. Checking that
+        // the queue is empty is not enough, since another sender may
+        // already have pushed its own value after ours was taken.
+        while self.inner.taken.load(Ordering::Relaxed) < ticket {
+            // Values are taken in order and the queue holds at most one,
+            // so the one left in it is ours.
+            if self.inner.no_receivers() {
+                let t = self.inner.withdraw(&mut que, level);
+                return Err(SendTimeoutError::Disconnected(t));
+            }
+            if expired(deadline) {
+                let t = self.inner.withdraw(&mut que, level);
+                return Err(SendTimeoutError::Timeout(t));
+            }
+            que = match que.wait_deadline(&self.inner.not_full, deadline) {
+                Ok(que) => que,
+                Err(err) => {
+                    let mut que = err.into_inner();
+                    let t = self.inner.withdraw(&mut que, level);
+                    return Err(SendTimeoutError::Disconnected(t));
+                }
+            };
+        }
+        Ok(())
+    }
+
+    // Waits for room in the channel and pushes convert(u), returning the
+    // lock along with the rendezvous ticket of the value. The caller still
+    // has to wake the RecvFutures. The conversion runs only once nothing can
+    // fail anymore, so every error hands u back as it was passed in.
+    fn push_deadline<U>(
+        &self,
+        u: U,
+        level: usize,
+        deadline: Option<Instant>,
+        convert: impl FnOnce(U) -> T,
+    ) -> Result<(PoisonGuard<'_, Queue<T>>, usize), SendTimeoutError<U>> {
+        // A poisoned channel is as good as disconnected: receivers refuse to
+        // take anything from it, so the value would never be received.
+        let mut que = match self.inner.shared.lock() {
+            Ok(que) => que,
+            Err(_) => return Err(SendTimeoutError::Disconnected(u)),
+        };
+        assert!(
+            level < que.levels(),
+            "priority level {} out of range",
+            level
+        );
+        // A rendezvous channel still needs room for the value being handed
+        // over, so it is allowed to hold a single one.
+        let bound = self.inner.capacity.map(|capacity| capacity.max(1));
+        loop {
+            // Refuse the value once the receivers are gone, otherwise queued
+            // values would pile up with nobody left to read them.
+            if self.is_disconnected() {
+                return Err(SendTimeoutError::Disconnected(u));
+            }
+            match bound {
+                Some(bound) if que.len() >= bound => {
+                    if expired(deadline) {
+                        return Err(SendTimeoutError::Timeout(u));
+                    }
+                    que = match que.wait_deadline(&self.inner.not_full, deadline) {
+                        Ok(que) => que,
+                        Err(_) => return Err(SendTimeoutError::Disconnected(u)),
+                    };
+                }
+                _ => break,
+            }
+        }
+        let ticket = self.inner.push(&mut que, level, convert(u));
+        Ok((que, ticket))
+    }
+
+    // Blocks until receivers have taken every value sent so far, e.g. to make
+    // sure a pipeline finished its work before shutting it down. Fails if the
+    // receivers are dropped before that, since the remaining values are then
+    // never going to be received.
+    pub fn wait_empty(&self) -> Result<(), WaitEmptyError> {
+        // Receivers take nothing from a poisoned channel, so it never drains.
+        let mut que = self.inner.shared.lock().map_err(|_| WaitEmptyError)?;
+        // Comparing the counters instead of checking the queue is empty makes
+        // values dropped along with the last receiver count as lost.
+        while self.inner.taken.load(Ordering::Relaxed) < self.inner.sent.load(Ordering::Relaxed) {
+            if self.inner.no_receivers() {
+                return Err(WaitEmptyError);
+            }
+            que = que.wait(&self.inner.empty).map_err(|_| WaitEmptyError)?;
+        }
+        Ok(())
+    }
+
+    // Sends a value only if the channel has room for it right away, handing
+    // it back otherwise. A rendezvous channel never has room, since its
+    // values are only handed over to a receiver by a blocking send.
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        // Poisoning counts as disconnected, see send_deadline.
+        let mut que = match self.inner.shared.lock() {
+            Ok(que) => que,
+            Err(_) => return Err(TrySendError::Disconnected(t)),
+        };
+        if self.is_disconnected() {
+            return Err(TrySendError::Disconnected(t));
+        }
+        match self.inner.capacity {
+            Some(capacity) if que.len() >= capacity => Err(TrySendError::Full(t)),
+            _ => {
+                self.inner.push(&mut que, 0, t);
+                drop(que);
+                self.inner.wake_futures();
+                Ok(())
+            }
+        }
+    }
+}
+
+fn expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+// A sender that does not count towards keeping the channel connected: once
+// every Sender is dropped the receivers see the channel as disconnected, even
+// if weak senders are still around.
+pub struct WeakSender<T> {
+    inner: Weak<Inner<T>>,
+}
+
+impl<T> WeakSender<T> {
+    // Returns a Sender if the channel still has other senders alive. Once
+    // the last one has been dropped the channel cannot be reconnected.
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        let inner = self.inner.upgrade()?;
+        let mut senders = inner.senders.load(Ordering::Acquire);
+        loop {
+            if senders == 0 {
+                return None;
+            }
+            match inner.senders.compare_exchange_weak(
+                senders,
+                senders + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(Sender { inner }),
+                Err(current) => senders = current,
+            }
+        }
+    }
+}
+
+impl<T> Clone for WeakSender<T> {
+    fn clone(&self) -> Self {
+        let inner = Weak::clone(&self.inner);
+        WeakSender { inner }
+    }
+}
+
+impl<T: Send + 'static> Sender<T> {
+    // Turns the sender into one that takes U values and converts them with f
+    // before sending. The channel's payload type is erased, so producers of
+    // U do not need to know about T.
+    //
+    // A failed send hands back the value unconverted, like Sender::send, so
+    // f only runs once the value is sure to be queued.
+    //
+    // Panics on a rendezvous channel: its send only succeeds once a receiver
+    // takes the value, and a value already converted could not be handed
+    // back if the receivers went away before that.
+    pub fn with<U, F>(self, f: F) -> MappedSender<U>
+    where
+        F: Fn(U) -> T + Send + Sync + 'static,
+    {
+        assert!(
+            self.inner.capacity != Some(0),
+            "Sender::with does not support rendezvous channels"
+        );
+        let send = move |u: U| {
+            let (que, _) = self
+                .push_deadline(u, 0, None, &f)
+                .map_err(|err| SendError(err.into_inner()))?;
+            drop(que);
+            self.inner.wake_futures();
+            Ok(())
+        };
+        MappedSender {
+            send: Arc::new(send),
+        }
+    }
+}
+
+// A sender that converts its values before sending them, see Sender::with.
+// Clones share the same underlying Sender.
+pub struct MappedSender<U> {
+    send: Arc<dyn Fn(U) -> Result<(), SendError<U>> + Send + Sync>,
+}
+
+impl<U> MappedSender<U> {
+    // Converts and sends a value, or hands it back unconverted if the
+    // receivers are gone, see Sender::with.
+    pub fn send(&self, u: U) -> Result<(), SendError<U>> {
+        (self.send)(u)
+    }
+}
+
+impl<U> Clone for MappedSender<U> {
+    fn clone(&self) -> Self {
+        let send = Arc::clone(&self.send);
+        MappedSender { send }
+    }
+}
+
+// Returned by send when the receiver has been dropped, carrying back the value
+// that could not be sent.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "sending on a closed channel".fmt(f)
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+// Returned by wait_empty when the receivers were dropped before taking every
+// value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitEmptyError;
+
+impl fmt::Display for WaitEmptyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "receivers dropped before the channel was drained".fmt(f)
+    }
+}
+
+impl Error for WaitEmptyError {}
+
+// Returned by try_send, carrying back the value that could not be sent.
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    // The bounded channel is at capacity.
+    Full(T),
+    // The receivers have been dropped, or the channel was closed.
+    Disconnected(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(t) | TrySendError::Disconnected(t) => t,
+        }
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => "Full(..)".fmt(f),
+            TrySendError::Disconnected(_) => "Disconnected(..)".fmt(f),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => "sending on a full channel".fmt(f),
+            TrySendError::Disconnected(_) => "sending on a closed channel".fmt(f),
+        }
+    }
+}
+
+impl<T> Error for TrySendError<T> {}
+
+// Returned by send_timeout, carrying back the value that could not be sent.
+#[derive(PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    // The channel had no room for the value before the timeout expired.
+    Timeout(T),
+    // The receivers have been dropped, or the channel was closed.
+    Disconnected(T),
+}
+
+impl<T> SendTimeoutError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendTimeoutError::Timeout(t) | SendTimeoutError::Disconnected(t) => t,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => "Timeout(..)".fmt(f),
+            SendTimeoutError::Disconnected(_) => "Disconnected(..)".fmt(f),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => "timed out waiting on send operation".fmt(f),
+            SendTimeoutError::Disconnected(_) => "sending on a closed channel".fmt(f),
+        }
+    }
+}
+
+impl<T> Error for SendTimeoutError<T> {}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let inner = Arc::clone(&self.inner);
+        Sender::new(inner)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Only the last sender has to wake anyone: until then the channel
+        // is still connected, so waiting receivers should keep waiting.
+        if self.inner.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Taking the lock makes sure that any receiver that saw us still
+            // connected is already waiting on the cvar, so it cannot miss the
+            // notification. Every receiver has to find out, not just one.
+            let que = self.inner.shared.lock();
+            self.inner.cvar.notify_all();
+            self.inner.notify_selectors();
+            drop(que);
+            self.inner.wake_futures();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvError {
+    // The channel is empty and all senders have been dropped.
+    Disconnected,
+    // A thread panicked while holding the channel's lock.
+    Poisoned,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Disconnected => "receiving on an empty and disconnected channel".fmt(f),
+            RecvError::Poisoned => "channel lock poisoned".fmt(f),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    // The channel is empty, but senders may still send values.
+    Empty,
+    // The channel is empty and all senders have been dropped.
+    Disconnected,
+    // A thread panicked while holding the channel's lock.
+    Poisoned,
 }
 
-impl<T> Inner<T> {
-    pub fn new() -> Self {
-        let shared = Mutex::new(VecDeque::new());
-        let cvar = Condvar::new();
-        Self { shared, cvar }
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => "receiving on an empty channel".fmt(f),
+            TryRecvError::Disconnected => "receiving on an empty and disconnected channel".fmt(f),
+            TryRecvError::Poisoned => "channel lock poisoned".fmt(f),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    // No value was sent before the timeout expired.
+    Timeout,
+    // The channel is empty and all senders have been dropped.
+    Disconnected,
+    // A thread panicked while holding the channel's lock.
+    Poisoned,
+}
+
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    fn new(inner: Arc<Inner<T>>) -> Self {
+        inner.receivers.fetch_add(1, Ordering::AcqRel);
+        Self { inner }
+    }
+
+    // Number of values waiting in the channel. This is a racy snapshot.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The maximum number of values the channel holds, or None if unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.inner.capacity
+    }
+
+    // Number of live senders and receivers of the channel. Like len, these
+    // are snapshots that other threads may change at any time.
+    pub fn sender_count(&self) -> usize {
+        self.inner.sender_count()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.inner.receiver_count()
+    }
+
+    // The largest number of values that were ever waiting in the channel at
+    // the same time, useful to pick the capacity of a bounded channel.
+    pub fn high_water_mark(&self) -> usize {
+        self.inner.high_water_mark.load(Ordering::Relaxed)
+    }
+
+    // True once every sender is gone or the channel was closed, so no more
+    // values will be sent. Values sent before that may still be waiting in
+    // the channel, and can be received as usual.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.no_senders()
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut que = self.inner.shared.lock().map_err(|_| RecvError::Poisoned)?;
+        while que.is_empty() {
+            // If there are no senders left, no more values will be
+            // received from this channel
+            if self.inner.no_senders() {
+                return Err(RecvError::Disconnected);
+            }
+            que = que
+                .wait(&self.inner.cvar)
+                .map_err(|_| RecvError::Poisoned)?;
+        }
+        let elem = self.inner.pop(&mut que).unwrap();
+        Ok(elem)
+    }
+
+    // Blocks like recv until at least one value is available, then moves up
+    // to max values into buf under a single lock acquisition. Returns how
+    // many values were received. With a max of 0 it returns right away.
+    pub fn recv_many(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        let mut que = self.inner.shared.lock().map_err(|_| RecvError::Poisoned)?;
+        while que.is_empty() {
+            if self.inner.no_senders() {
+                return Err(RecvError::Disconnected);
+            }
+            que = que
+                .wait(&self.inner.cvar)
+                .map_err(|_| RecvError::Poisoned)?;
+        }
+        let count = que.len().min(max);
+        buf.reserve(count);
+        for _ in 0..count {
+            buf.push(self.inner.pop(&mut que).unwrap());
+        }
+        Ok(count)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            // A timeout this long can never expire
+            None => {
+                return self.recv().map_err(|err| match err {
+                    RecvError::Disconnected => RecvTimeoutError::Disconnected,
+                    RecvError::Poisoned => RecvTimeoutError::Poisoned,
+                })
+            }
+        };
+        self.recv_deadline(deadline)
+    }
+
+    // Like recv_timeout, but waits until an absolute point in time. Several
+    // calls can share the same deadline without the timeouts adding up.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let mut que = self
+            .inner
+            .shared
+            .lock()
+            .map_err(|_| RecvTimeoutError::Poisoned)?;
+        loop {
+            if let Some(elem) = self.inner.pop(&mut que) {
+                return Ok(elem);
+            }
+            if self.inner.no_senders() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            // Recompute what is left on every iteration, so spurious wakeups
+            // do not extend the total time we wait.
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            que = que
+                .wait_timeout(&self.inner.cvar, deadline - now)
+                .map_err(|_| RecvTimeoutError::Poisoned)?;
+        }
+    }
+
+    // Like recv, but instead of blocking the thread the returned future is
+    // woken up once a value is sent or the last sender is dropped.
+    pub fn recv_async(&self) -> RecvFuture<'_, T> {
+        RecvFuture::new(self)
+    }
+
+    // Returns an iterator that blocks waiting for values, and ends once all
+    // senders have been dropped.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    // Returns an iterator over the values already in the channel, which ends
+    // as soon as it finds the channel empty instead of waiting for more.
+    // After it ends, TryIter::is_disconnected tells whether more values may
+    // still arrive.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter {
+            receiver: self,
+            disconnected: false,
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut que = self
+            .inner
+            .shared
+            .lock()
+            .map_err(|_| TryRecvError::Poisoned)?;
+        match self.inner.pop(&mut que) {
+            Some(elem) => Ok(elem),
+            None if self.inner.no_senders() => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    // Returns a copy of the value recv would return next, leaving it in the
+    // channel. Another receiver may still take it before we do.
+    pub fn peek(&self) -> Result<T, TryRecvError>
+    where
+        T: Clone,
+    {
+        self.peek_with(T::clone)
+    }
+
+    // Like peek, but calls f on the next value in place instead of cloning it.
+    // The channel stays locked while f runs, so it should be short. f only
+    // gets to read the value, so a panic in it does not poison the channel.
+    pub fn peek_with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Result<R, TryRecvError> {
+        let que = self
+            .inner
+            .shared
+            .lock()
+            .map_err(|_| TryRecvError::Poisoned)?
+            .read_only();
+        match que.front() {
+            Some(elem) => Ok(f(elem)),
+            None if self.inner.no_senders() => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+pub struct Iter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+pub struct TryIter<'a, T> {
+    receiver: &'a Receiver<T>,
+    disconnected: bool,
+}
+
+impl<'a, T> TryIter<'a, T> {
+    // True if the iterator ended because all senders were gone, rather than
+    // because the channel was momentarily empty.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+}
+
+impl<'a, T> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self.receiver.try_recv() {
+            Ok(elem) => Some(elem),
+            Err(TryRecvError::Empty) => None,
+            // A poisoned channel never yields a value again either.
+            Err(TryRecvError::Disconnected) | Err(TryRecvError::Poisoned) => {
+                self.disconnected = true;
+                None
+            }
+        }
+    }
+}
+
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+// Receivers can be cloned to share the work of draining a channel: every
+// value is still received exactly once, by whichever receiver gets to it first.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let inner = Arc::clone(&self.inner);
+        Receiver::new(inner)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.inner.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Taking the lock makes sure that any sender that saw receivers
+            // still alive is already waiting on not_full, so it cannot miss
+            // the notification.
+            let mut que = self
+                .inner
+                .shared
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            self.inner.not_full.notify_all();
+            self.inner.empty.notify_all();
+
+            // Nobody can receive the queued values anymore, so drop them now
+            // instead of when the last sender goes away. A rendezvous channel
+            // is the exception: its value still belongs to the blocked sender,
+            // which gets it back in its SendError.
+            if self.inner.capacity == Some(0) {
+                return;
+            }
+            let unreceived: Vec<T> = std::iter::from_fn(|| que.pop_front()).collect();
+            // Run the destructors without holding the lock.
+            drop(que);
+            drop(unreceived);
+        }
+    }
+}
+
+fn pair<T>(inner: Inner<T>) -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(inner);
+    (
+        Sender::new(Arc::clone(&inner)),
+        Receiver::new(Arc::clone(&inner)),
+    )
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    pair(Inner::new())
+}
+
+// Creates an unbounded channel with room for capacity values allocated up
+// front. It behaves exactly like channel(), the capacity is only a hint to
+// avoid growing the queue during bursts.
+pub fn channel_with_capacity<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    pair(Inner::preallocated(capacity))
+}
+
+// Creates a channel that holds at most capacity values. Sending on a full
+// channel blocks until the receiver makes room. With a capacity of 0, every
+// send blocks until the receiver has taken the value.
+pub fn bounded_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    pair(Inner::bounded(capacity))
+}
+
+// The std::sync::mpsc name for a bounded channel. A bound of 0 gives a
+// rendezvous channel, where send only returns once the value was received.
+pub fn sync_channel<T>(bound: usize) -> (Sender<T>, Receiver<T>) {
+    bounded_channel(bound)
+}
+
+// Creates an unbounded channel with the given number of priority levels,
+// see Sender::send_with_priority.
+pub fn priority_channel<T>(levels: usize) -> (Sender<T>, Receiver<T>) {
+    pair(Inner::prioritized(levels))
+}
+
+#[cfg(test)]
+mod tests {
+    struct DummyPayload {}
+
+    impl DummyPayload {
+        fn new() -> Self {
+            Self {}
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct DummyPayloadWithValue {
+        internal: u32,
+    }
+
+    impl DummyPayloadWithValue {
+        fn new(internal: u32) -> Self {
+            Self { internal }
+        }
+    }
+
+    use super::*;
+    use std::sync::{Condvar, Mutex};
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn test_sender_basic_api() {
+        let payload1 = DummyPayload::new();
+        let payload2 = DummyPayload::new();
+        let (sender, _receiver) = channel();
+        sender.send(payload1).unwrap();
+        sender.send(payload2).unwrap();
+    }
+
+    #[test]
+    fn test_receiver_basic_api() {
+        let (sender, receiver): (_, Receiver<DummyPayload>) = channel();
+        sender.send(DummyPayload::new()).unwrap();
+        let _: DummyPayload = receiver.recv().unwrap();
+    }
+
+    #[test]
+    fn test_recv_returns_sent_value() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = channel();
+
+        sender.send(payload).unwrap();
+        let received = receiver.recv().unwrap();
+
+        assert_eq!(received, payload);
+    }
+
+    #[test]
+    fn test_sender_can_be_sent_to_threads() {
+        let (sender, _receiver) = channel();
+
+        spawn(move || {
+            sender.send(DummyPayload::new()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_receiver_can_be_sent_to_threads() {
+        let (_, receiver): (_, Receiver<DummyPayload>) = channel();
+
+        spawn(move || {
+            let _ = receiver.recv().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_no_receive_without_send() {
+        let (sender, receiver): (_, Receiver<DummyPayload>) = channel();
+        let finished_flag = Arc::new(Mutex::new(false));
+        let finished_flag2 = Arc::clone(&finished_flag);
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let pair2 = Arc::clone(&pair);
+
+        spawn(move || {
+            let (lock, cvar) = &*pair2;
+            let mut started = lock.lock().unwrap();
+            *started = true;
+            drop(started);
+            cvar.notify_one();
+
+            let res = receiver.recv().unwrap();
+            let mut finished = finished_flag2.lock().unwrap();
+            *finished = true;
+            res
+        });
+
+        let (lock, cvar) = &*pair;
+        let mut started = lock.lock().unwrap();
+
+        while !*started {
+            started = cvar.wait(started).unwrap();
+        }
+
+        sleep(Duration::from_millis(2000));
+        if *finished_flag.lock().unwrap() {
+            panic!("Spawned thread did not block on recv");
+        }
+
+        sender.send(DummyPayload::new()).unwrap();
+
+        sleep(Duration::from_millis(2000));
+        if !*finished_flag.lock().unwrap() {
+            panic!("Spawned thread did not finish");
+        }
+    }
+
+    #[test]
+    fn test_recv_returns_sent_value_threaded() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = channel();
+
+        spawn(move || {
+            sender.send(payload).unwrap();
+        });
+
+        let handle = spawn(move || {
+            sleep(Duration::from_millis(1000));
+            let received = receiver.recv().unwrap();
+            assert_eq!(received, payload);
+        });
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_cloning_works() {
+        let target_payload = DummyPayloadWithValue::new(32);
+        let (sender, receiver) = channel();
+        let sender2 = sender.clone();
+
+        sender2.send(DummyPayloadWithValue::new(32)).unwrap();
+        assert_eq!(receiver.recv().unwrap(), target_payload);
+    }
+
+    #[test]
+    fn test_no_hang_on_dropped_sender() {
+        let (sender, receiver): (Sender<DummyPayload>, Receiver<DummyPayload>) = channel();
+        let s2 = sender.clone();
+        drop(sender);
+
+        s2.send(DummyPayload::new()).unwrap();
+        assert!(receiver.recv().is_ok());
+
+        drop(s2);
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn test_drop_senders_wakes_receiver() {
+        let (sender, receiver): (Sender<DummyPayload>, _) = channel();
+        let sender2 = sender.clone();
+        let finished = Arc::new(Mutex::new(false));
+        let finished2 = Arc::clone(&finished);
+
+        spawn(move || match receiver.recv() {
+            Ok(_) => panic!("received value when it shouldn't"),
+            Err(err) => {
+                assert_eq!(err, RecvError::Disconnected);
+                *finished2.lock().unwrap() = true;
+            }
+        });
+
+        sleep(Duration::from_millis(1000));
+        assert!(!*finished.lock().unwrap());
+
+        drop(sender);
+        sleep(Duration::from_millis(1000));
+        assert!(!*finished.lock().unwrap());
+
+        drop(sender2);
+        sleep(Duration::from_millis(1000));
+        assert!(*finished.lock().unwrap());
+    }
+
+    #[test]
+    fn test_bounded_send_blocks_when_full() {
+        let (sender, receiver) = bounded_channel(2);
+        let finished = Arc::new(Mutex::new(false));
+        let finished2 = Arc::clone(&finished);
+
+        sender.send(DummyPayloadWithValue::new(1)).unwrap();
+        sender.send(DummyPayloadWithValue::new(2)).unwrap();
+
+        spawn(move || {
+            sender.send(DummyPayloadWithValue::new(3)).unwrap();
+            *finished2.lock().unwrap() = true;
+        });
+
+        sleep(Duration::from_millis(1000));
+        assert!(!*finished.lock().unwrap());
+
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(1));
+        sleep(Duration::from_millis(1000));
+        assert!(*finished.lock().unwrap());
+
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(2));
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(3));
+    }
+
+    #[test]
+    fn test_rendezvous_send_waits_for_recv() {
+        let (sender, receiver) = bounded_channel(0);
+        let finished = Arc::new(Mutex::new(false));
+        let finished2 = Arc::clone(&finished);
+
+        spawn(move || {
+            sender.send(DummyPayloadWithValue::new(1)).unwrap();
+            *finished2.lock().unwrap() = true;
+        });
+
+        sleep(Duration::from_millis(1000));
+        assert!(!*finished.lock().unwrap());
+
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(1));
+        sleep(Duration::from_millis(1000));
+        assert!(*finished.lock().unwrap());
+    }
+
+    #[test]
+    fn test_drop_receiver_unblocks_bounded_sender() {
+        let (sender, receiver) = bounded_channel(1);
+        sender.send(DummyPayload::new()).unwrap();
+
+        let handle = spawn(move || sender.send(DummyPayload::new()));
+
+        sleep(Duration::from_millis(1000));
+        drop(receiver);
+
+        assert!(handle.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_try_recv_empty() {
+        let (_sender, receiver): (_, Receiver<DummyPayload>) = channel();
+        assert_eq!(receiver.try_recv().err(), Some(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_try_recv_disconnected() {
+        let (sender, receiver): (_, Receiver<DummyPayload>) = channel();
+        drop(sender);
+        assert_eq!(receiver.try_recv().err(), Some(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_try_recv_returns_sent_value() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = channel();
+
+        sender.send(payload).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(payload));
+
+        // Values sent before the senders are dropped can still be received
+        sender.send(payload).unwrap();
+        drop(sender);
+        assert_eq!(receiver.try_recv(), Ok(payload));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_recv_timeout_times_out() {
+        let (_sender, receiver): (_, Receiver<DummyPayload>) = channel();
+
+        let start = Instant::now();
+        let res = receiver.recv_timeout(Duration::from_millis(500));
+
+        assert_eq!(res.err(), Some(RecvTimeoutError::Timeout));
+        assert!(start.elapsed() >= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_recv_timeout_returns_value_sent_in_time() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = channel();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            sender.send(payload).unwrap();
+        });
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(payload));
+    }
+
+    #[test]
+    fn test_recv_timeout_disconnected() {
+        let (sender, receiver): (_, Receiver<DummyPayload>) = channel();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            drop(sender);
+        });
+
+        let res = receiver.recv_timeout(Duration::from_secs(5));
+        assert_eq!(res.err(), Some(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn test_iter_ends_when_senders_dropped() {
+        let (sender, receiver) = channel();
+        for i in 0..3 {
+            sender.send(DummyPayloadWithValue::new(i)).unwrap();
+        }
+        drop(sender);
+
+        let received: Vec<_> = receiver.iter().collect();
+        assert_eq!(
+            received,
+            (0..3).map(DummyPayloadWithValue::new).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_into_iter_threaded() {
+        let (sender, receiver) = channel();
+
+        spawn(move || {
+            for i in 0..3 {
+                sleep(Duration::from_millis(100));
+                sender.send(i).unwrap();
+            }
+        });
+
+        let mut received = Vec::new();
+        for i in receiver {
+            received.push(i);
+        }
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_send_after_receiver_dropped_returns_value() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = channel();
+        drop(receiver);
+
+        assert_eq!(sender.send(payload), Err(SendError(payload)));
+    }
+
+    #[test]
+    fn test_bounded_send_after_receiver_dropped_returns_value() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = bounded_channel(1);
+        sender.send(DummyPayloadWithValue::new(1)).unwrap();
+
+        let handle = spawn(move || sender.send(payload));
+        sleep(Duration::from_millis(500));
+        drop(receiver);
+
+        assert_eq!(handle.join().unwrap(), Err(SendError(payload)));
+    }
+
+    #[test]
+    fn test_rendezvous_send_returns_value_not_taken() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = bounded_channel(0);
+
+        let handle = spawn(move || sender.send(payload));
+        sleep(Duration::from_millis(500));
+        drop(receiver);
+
+        assert_eq!(handle.join().unwrap(), Err(SendError(payload)));
+    }
+
+    #[test]
+    fn test_recv_error_composes_with_question_mark() {
+        fn recv_boxed(receiver: &Receiver<u32>) -> Result<u32, Box<dyn Error>> {
+            Ok(receiver.recv()?)
+        }
+
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        drop(sender);
+
+        assert_eq!(recv_boxed(&receiver).unwrap(), 1);
+        let err = recv_boxed(&receiver).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "receiving on an empty and disconnected channel"
+        );
+    }
+
+    #[test]
+    fn test_recv_poisoned() {
+        let (sender, receiver) = channel::<u32>();
+        let inner = Arc::clone(&receiver.inner);
+
+        let _ = spawn(move || {
+            let _que = inner.shared.lock().unwrap();
+            panic!("poison the channel lock");
+        })
+        .join();
+
+        assert_eq!(receiver.recv(), Err(RecvError::Poisoned));
+        drop(sender);
+    }
+
+    #[test]
+    fn test_every_path_reports_poisoning() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        let inner = Arc::clone(&receiver.inner);
+
+        let _ = spawn(move || {
+            let _que = inner.shared.lock().unwrap();
+            panic!("poison the channel lock");
+        })
+        .join();
+
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Poisoned));
+        assert_eq!(receiver.peek(), Err(TryRecvError::Poisoned));
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(100)),
+            Err(RecvTimeoutError::Poisoned)
+        );
+        assert_eq!(
+            receiver.recv_timeout(Duration::MAX),
+            Err(RecvTimeoutError::Poisoned)
+        );
+        assert_eq!(
+            receiver.recv_many(&mut Vec::new(), 1),
+            Err(RecvError::Poisoned)
+        );
+        // Senders get their value back, it could never be received
+        assert_eq!(sender.send(2), Err(SendError(2)));
+        assert_eq!(sender.try_send(3), Err(TrySendError::Disconnected(3)));
+        assert_eq!(sender.wait_empty(), Err(WaitEmptyError));
+        assert_eq!(receiver.len(), 1);
+    }
+
+    #[test]
+    fn test_send_fails_fast_with_other_senders_alive() {
+        let (sender, receiver) = channel();
+        let sender2 = sender.clone();
+        drop(receiver);
+
+        assert!(sender.send(DummyPayload::new()).is_err());
+        assert!(sender2.send(DummyPayload::new()).is_err());
+        assert!(sender.inner.shared.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_len_counts_queued_messages() {
+        const N: usize = 5;
+        let (sender, receiver) = channel();
+        assert!(sender.is_empty());
+        assert!(receiver.is_empty());
+
+        for _ in 0..N {
+            sender.send(DummyPayload::new()).unwrap();
+        }
+        assert_eq!(sender.len(), N);
+        assert_eq!(receiver.len(), N);
+        assert!(!receiver.is_empty());
+
+        receiver.recv().unwrap();
+        assert_eq!(receiver.len(), N - 1);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let (sender, receiver) = channel::<DummyPayload>();
+        assert_eq!(sender.capacity(), None);
+        assert_eq!(receiver.capacity(), None);
+
+        let (sender, receiver) = bounded_channel::<DummyPayload>(3);
+        assert_eq!(sender.capacity(), Some(3));
+        assert_eq!(receiver.capacity(), Some(3));
+    }
+
+    #[test]
+    fn test_cloned_receivers_share_the_queue() {
+        use std::collections::HashSet;
+
+        const NUM_RECEIVERS: usize = 4;
+        const NUM_ITEMS: u32 = 1000;
+
+        let (sender, receiver) = channel();
+        let handles: Vec<_> = (0..NUM_RECEIVERS)
+            .map(|_| {
+                let receiver = receiver.clone();
+                spawn(move || receiver.iter().collect::<Vec<u32>>())
+            })
+            .collect();
+        drop(receiver);
+
+        for i in 0..NUM_ITEMS {
+            sender.send(i).unwrap();
+        }
+        drop(sender);
+
+        let mut seen = HashSet::new();
+        for handle in handles {
+            for i in handle.join().unwrap() {
+                assert!(seen.insert(i), "{} was received twice", i);
+            }
+        }
+        assert_eq!(seen.len(), NUM_ITEMS as usize);
+    }
+
+    #[test]
+    fn test_channel_stays_connected_while_a_receiver_lives() {
+        let (sender, receiver) = channel();
+        let receiver2 = receiver.clone();
+
+        drop(receiver);
+        sender.send(DummyPayloadWithValue::new(1)).unwrap();
+        assert_eq!(receiver2.recv().unwrap(), DummyPayloadWithValue::new(1));
+
+        drop(receiver2);
+        assert!(sender.send(DummyPayloadWithValue::new(2)).is_err());
+    }
+
+    #[test]
+    fn test_sync_channel_send_returns_after_recv() {
+        let payload = DummyPayloadWithValue::new(4123);
+        let (sender, receiver) = sync_channel(0);
+
+        let handle = spawn(move || {
+            sleep(Duration::from_millis(500));
+            receiver.recv().unwrap()
+        });
+
+        let start = Instant::now();
+        sender.send(payload).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(sender.is_empty());
+
+        assert_eq!(handle.join().unwrap(), payload);
+    }
+
+    #[test]
+    fn test_rendezvous_senders_get_their_own_value_back() {
+        let (sender, receiver) = sync_channel(0);
+        let sender2 = sender.clone();
+
+        let first = spawn(move || sender.send(DummyPayloadWithValue::new(1)));
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(1));
+
+        let second = spawn(move || sender2.send(DummyPayloadWithValue::new(2)));
+        sleep(Duration::from_millis(500));
+        drop(receiver);
+
+        assert_eq!(first.join().unwrap(), Ok(()));
+        assert_eq!(
+            second.join().unwrap(),
+            Err(SendError(DummyPayloadWithValue::new(2)))
+        );
+    }
+
+    #[test]
+    fn test_weak_sender_upgrade_while_connected() {
+        let (sender, receiver) = channel();
+        let weak = sender.downgrade();
+
+        let upgraded = weak.upgrade().unwrap();
+        drop(sender);
+        upgraded.send(DummyPayloadWithValue::new(1)).unwrap();
+
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(1));
+    }
+
+    #[test]
+    fn test_weak_sender_does_not_keep_channel_alive() {
+        let (sender, receiver): (_, Receiver<DummyPayload>) = channel();
+        let sender2 = sender.clone();
+        let weak = sender.downgrade();
+
+        drop(sender);
+        drop(sender2);
+
+        assert_eq!(receiver.recv().err(), Some(RecvError::Disconnected));
+        assert!(weak.upgrade().is_none());
     }
-}
 
-pub struct Sender<T> {
-    inner: Arc<Inner<T>>,
-}
+    #[test]
+    fn test_priority_channel_delivers_urgent_first() {
+        let (sender, receiver) = priority_channel(3);
+        for i in 0..3 {
+            sender.send(DummyPayloadWithValue::new(i)).unwrap();
+        }
+        sender
+            .send_with_priority(DummyPayloadWithValue::new(100), 2)
+            .unwrap();
+        sender
+            .send_with_priority(DummyPayloadWithValue::new(50), 1)
+            .unwrap();
 
-impl<T> Sender<T> {
-    fn new(inner: Arc<Inner<T>>) -> Self {
-        Self { inner }
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(100));
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(50));
+        for i in 0..3 {
+            assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(i));
+        }
     }
 
-    pub fn send(&self, t: T) -> Result<(), ()> {
-        self.inner.shared.lock().unwrap().push_back(t);
-        self.inner.cvar.notify_one();
-        Ok(())
+    #[test]
+    #[should_panic]
+    fn test_send_with_priority_out_of_range() {
+        let (sender, _receiver) = channel();
+        let _ = sender.send_with_priority(DummyPayload::new(), 1);
     }
-}
 
-impl<T> Clone for Sender<T> {
-    fn clone(&self) -> Self {
-        let inner = Arc::clone(&self.inner);
-        Sender::new(inner)
+    #[test]
+    fn test_recv_many_drains_batch() {
+        let (sender, receiver) = channel();
+        for i in 0..10 {
+            sender.send(DummyPayloadWithValue::new(i)).unwrap();
+        }
+
+        let mut buf = Vec::new();
+        assert_eq!(receiver.recv_many(&mut buf, 100), Ok(10));
+        let expected: Vec<_> = (0..10).map(DummyPayloadWithValue::new).collect();
+        assert_eq!(buf, expected);
+        assert!(receiver.is_empty());
     }
-}
 
-impl<T> Drop for Sender<T> {
-    fn drop(&mut self) {
-        // If strong_count is 2, this means there are two strong references
-        // to the inner struct. One is us. If the other is another sender,
-        // then it doesn't matter if we notify the cvar, since no one is
-        // waiting on it.
-        // If the other is the receiver, then it is safe to notify them,
-        // since there will be no other senders after we are droped.
-        if Arc::strong_count(&self.inner) == 2 {
-            self.inner.cvar.notify_one();
+    #[test]
+    fn test_recv_many_respects_max() {
+        let (sender, receiver) = channel();
+        for i in 0..10 {
+            sender.send(i).unwrap();
         }
+
+        let mut buf = Vec::new();
+        assert_eq!(receiver.recv_many(&mut buf, 4), Ok(4));
+        assert_eq!(buf, vec![0, 1, 2, 3]);
+        assert_eq!(receiver.len(), 6);
+
+        drop(sender);
+        assert_eq!(receiver.recv_many(&mut buf, 100), Ok(6));
+        assert_eq!(
+            receiver.recv_many(&mut buf, 100),
+            Err(RecvError::Disconnected)
+        );
     }
-}
 
-pub struct Receiver<T> {
-    inner: Arc<Inner<T>>,
-}
+    #[test]
+    fn test_peek_returns_next_value() {
+        let (sender, receiver) = channel();
+        assert_eq!(receiver.peek(), Err(TryRecvError::Empty));
 
-impl<T> Receiver<T> {
-    fn new(inner: Arc<Inner<T>>) -> Self {
-        Self { inner }
+        sender.send(DummyPayloadWithValue::new(1)).unwrap();
+        sender.send(DummyPayloadWithValue::new(2)).unwrap();
+
+        assert_eq!(receiver.peek(), Ok(DummyPayloadWithValue::new(1)));
+        assert_eq!(receiver.peek_with(|payload| payload.internal), Ok(1));
+        assert_eq!(receiver.len(), 2);
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(1));
+        assert_eq!(receiver.peek(), Ok(DummyPayloadWithValue::new(2)));
+
+        drop(sender);
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(2));
+        assert_eq!(receiver.peek(), Err(TryRecvError::Disconnected));
     }
 
-    pub fn recv(&self) -> Result<T, &'static str> {
-        let mut que = self.inner.shared.lock().map_err(|_| "lock error")?;
-        while que.is_empty() {
-            // If strong_count is 1, it means that there are no other
-            // senders, no more values will be received from this channel
-            if Arc::strong_count(&self.inner) == 1 {
-                return Err("no more values");
+    #[test]
+    fn test_peek_with_panic_does_not_poison() {
+        let (sender, receiver) = channel();
+        sender.send(DummyPayloadWithValue::new(1)).unwrap();
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            receiver.peek_with(|_| panic!("panic while peeking"))
+        }));
+        assert!(res.is_err());
+
+        sender.send(DummyPayloadWithValue::new(2)).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(DummyPayloadWithValue::new(1)));
+        assert_eq!(receiver.recv(), Ok(DummyPayloadWithValue::new(2)));
+    }
+
+    #[test]
+    fn test_close_drains_then_disconnects() {
+        let (sender, receiver) = channel();
+        let sender2 = sender.clone();
+        sender.send(DummyPayloadWithValue::new(1)).unwrap();
+        sender2.send(DummyPayloadWithValue::new(2)).unwrap();
+
+        sender.close();
+        assert_eq!(
+            sender2.send(DummyPayloadWithValue::new(3)),
+            Err(SendError(DummyPayloadWithValue::new(3)))
+        );
+
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(1));
+        assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(2));
+        assert_eq!(receiver.recv(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_close_wakes_receiver() {
+        let (sender, receiver) = channel::<u32>();
+
+        let handle = spawn(move || {
+            sleep(Duration::from_millis(500));
+            sender.close();
+            // Still holding the sender, the receiver must wake up anyway
+            sleep(Duration::from_millis(1000));
+        });
+
+        assert_eq!(receiver.recv(), Err(RecvError::Disconnected));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_close_unblocks_bounded_sender() {
+        let (sender, _receiver) = bounded_channel(1);
+        sender.send(1).unwrap();
+
+        let closer = sender.clone();
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            closer.close();
+        });
+
+        assert_eq!(sender.send(2), Err(SendError(2)));
+    }
+
+    #[test]
+    fn test_try_recv_poll_loop() {
+        let (sender, receiver) = channel();
+
+        spawn(move || {
+            for i in 0..3 {
+                sleep(Duration::from_millis(100));
+                sender.send(i).unwrap();
+            }
+        });
+
+        // Keep polling through Empty, stop only on Disconnected
+        let mut received = Vec::new();
+        let mut empty_polls = 0;
+        loop {
+            match receiver.try_recv() {
+                Ok(elem) => received.push(elem),
+                Err(TryRecvError::Empty) => {
+                    empty_polls += 1;
+                    sleep(Duration::from_millis(10));
+                }
+                Err(TryRecvError::Disconnected) | Err(TryRecvError::Poisoned) => break,
             }
-            que = self.inner.cvar.wait(que).map_err(|_| "wait error")?;
         }
-        let elem = que.pop_front().unwrap();
-        Ok(elem)
+        assert_eq!(received, vec![0, 1, 2]);
+        assert!(empty_polls > 0);
     }
-}
 
-pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
-    let inner = Arc::new(Inner::new());
-    (
-        Sender::new(Arc::clone(&inner)),
-        Receiver::new(Arc::clone(&inner)),
-    )
-}
+    #[test]
+    fn test_try_recv_error_display() {
+        assert_eq!(
+            TryRecvError::Empty.to_string(),
+            "receiving on an empty channel"
+        );
+        assert_eq!(
+            TryRecvError::Disconnected.to_string(),
+            RecvError::Disconnected.to_string()
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    struct DummyPayload {}
+    #[test]
+    fn test_recv_deadline_in_the_past() {
+        let (_sender, receiver) = channel::<u32>();
+        let deadline = Instant::now();
+        sleep(Duration::from_millis(10));
 
-    impl DummyPayload {
-        fn new() -> Self {
-            Self {}
-        }
+        let start = Instant::now();
+        assert_eq!(
+            receiver.recv_deadline(deadline),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() < Duration::from_millis(100));
     }
 
-    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-    struct DummyPayloadWithValue {
-        internal: u32,
+    #[test]
+    fn test_recv_deadline_shared_between_calls() {
+        let (sender, receiver) = channel();
+        let deadline = Instant::now() + Duration::from_millis(500);
+
+        spawn(move || {
+            sender.send(1).unwrap();
+            sleep(Duration::from_millis(2000));
+        });
+
+        assert_eq!(receiver.recv_deadline(deadline), Ok(1));
+        assert_eq!(
+            receiver.recv_deadline(deadline),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(Instant::now() >= deadline);
     }
 
-    impl DummyPayloadWithValue {
-        fn new(internal: u32) -> Self {
-            Self { internal }
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
         }
     }
 
-    use super::*;
-    use std::sync::Condvar;
-    use std::thread::{sleep, spawn};
-    use std::time::Duration;
+    #[test]
+    fn test_drop_receiver_drops_queued_values() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = channel();
+        for _ in 0..3 {
+            sender.send(DropCounter(Arc::clone(&dropped))).unwrap();
+        }
+
+        drop(receiver);
+        // The sender is still alive, yet the values are gone
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+        assert!(sender.is_empty());
+        assert!(sender.send(DropCounter(Arc::clone(&dropped))).is_err());
+        assert_eq!(dropped.load(Ordering::Relaxed), 4);
+    }
 
     #[test]
-    fn test_sender_basic_api() {
-        let payload1 = DummyPayload::new();
-        let payload2 = DummyPayload::new();
-        let (sender, _) = channel();
-        sender.send(payload1).unwrap();
-        sender.send(payload2).unwrap();
+    fn test_sender_and_receiver_counts() {
+        let (sender, receiver) = channel::<u32>();
+        assert_eq!(sender.sender_count(), 1);
+        assert_eq!(sender.receiver_count(), 1);
+
+        let sender2 = sender.clone();
+        let sender3 = sender2.clone();
+        assert_eq!(receiver.sender_count(), 3);
+
+        drop(sender2);
+        assert_eq!(receiver.sender_count(), 2);
+
+        // Weak senders are not counted
+        let weak = sender3.downgrade();
+        assert_eq!(receiver.sender_count(), 2);
+        drop(sender3);
+        assert_eq!(receiver.sender_count(), 1);
+        drop(weak);
+
+        let receiver2 = receiver.clone();
+        assert_eq!(sender.receiver_count(), 2);
+        drop(receiver);
+        drop(receiver2);
+        assert_eq!(sender.receiver_count(), 0);
     }
 
     #[test]
-    fn test_receiver_basic_api() {
-        let (sender, receiver): (_, Receiver<DummyPayload>) = channel();
-        sender.send(DummyPayload::new()).unwrap();
-        let _: DummyPayload = receiver.recv().unwrap();
+    fn test_try_send_full() {
+        let (sender, receiver) = bounded_channel(2);
+        assert_eq!(sender.try_send(1), Ok(()));
+        assert_eq!(sender.try_send(2), Ok(()));
+        assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(sender.try_send(3), Ok(()));
+        assert_eq!(receiver.recv(), Ok(2));
+        assert_eq!(receiver.recv(), Ok(3));
     }
 
     #[test]
-    fn test_recv_returns_sent_value() {
-        let payload = DummyPayloadWithValue::new(4123);
-        let (sender, receiver) = channel();
+    fn test_try_send_disconnected() {
+        let (sender, receiver) = bounded_channel(2);
+        drop(receiver);
+        assert_eq!(sender.try_send(1), Err(TrySendError::Disconnected(1)));
 
-        sender.send(payload).unwrap();
-        let received = receiver.recv().unwrap();
+        // An unbounded channel is never full
+        let (sender, _receiver) = channel();
+        for i in 0..100 {
+            assert_eq!(sender.try_send(i), Ok(()));
+        }
 
-        assert_eq!(received, payload);
+        // A rendezvous channel never has room
+        let (sender, _receiver) = sync_channel(0);
+        assert_eq!(sender.try_send(1), Err(TrySendError::Full(1)));
     }
 
     #[test]
-    fn test_sender_can_be_sent_to_threads() {
-        let (sender, _) = channel();
+    fn test_send_timeout_times_out_when_full() {
+        let (sender, receiver) = bounded_channel(1);
+        sender.send(1).unwrap();
 
-        spawn(move || {
-            sender.send(DummyPayload::new()).unwrap();
-        });
+        let start = Instant::now();
+        assert_eq!(
+            sender.send_timeout(2, Duration::from_millis(200)),
+            Err(SendTimeoutError::Timeout(2))
+        );
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(sender.send_timeout(2, Duration::from_millis(200)), Ok(()));
+        assert_eq!(receiver.recv(), Ok(2));
+
+        drop(receiver);
+        assert_eq!(
+            sender.send_timeout(3, Duration::from_millis(200)),
+            Err(SendTimeoutError::Disconnected(3))
+        );
     }
 
     #[test]
-    fn test_receiver_can_be_sent_to_threads() {
-        let (_, receiver): (_, Receiver<DummyPayload>) = channel();
+    fn test_send_timeout_rendezvous() {
+        let (sender, receiver) = sync_channel(0);
+        assert_eq!(
+            sender.send_timeout(1, Duration::from_millis(200)),
+            Err(SendTimeoutError::Timeout(1))
+        );
+        assert!(receiver.is_empty());
 
+        // A timed out send must not throw off the handshake of later ones
         spawn(move || {
-            let _ = receiver.recv().unwrap();
+            sleep(Duration::from_millis(200));
+            assert_eq!(receiver.recv(), Ok(2));
         });
+        assert_eq!(sender.send_timeout(2, Duration::from_secs(5)), Ok(()));
     }
 
     #[test]
-    fn test_no_receive_without_send() {
-        let (sender, receiver): (_, Receiver<DummyPayload>) = channel();
-        let finished_flag = Arc::new(Mutex::new(false));
-        let finished_flag2 = Arc::clone(&finished_flag);
-        let pair = Arc::new((Mutex::new(false), Condvar::new()));
-        let pair2 = Arc::clone(&pair);
+    fn test_mapped_sender_converts_values() {
+        let (sender, receiver) = channel::<String>();
+        let mapped = sender.with(|n: u32| n.to_string());
+        let mapped2 = mapped.clone();
 
         spawn(move || {
-            let (lock, cvar) = &*pair2;
-            let mut started = lock.lock().unwrap();
-            *started = true;
-            drop(started);
-            cvar.notify_one();
-
-            let res = receiver.recv().unwrap();
-            let mut finished = finished_flag2.lock().unwrap();
-            *finished = true;
-            res
+            mapped.send(1).unwrap();
+            mapped2.send(42).unwrap();
         });
 
-        let (lock, cvar) = &*pair;
-        let mut started = lock.lock().unwrap();
+        assert_eq!(receiver.recv().unwrap(), "1");
+        assert_eq!(receiver.recv().unwrap(), "42");
+        // The mapped senders were the only ones left
+        assert_eq!(receiver.recv(), Err(RecvError::Disconnected));
+    }
 
-        while !*started {
-            started = cvar.wait(started).unwrap();
-        }
+    #[test]
+    fn test_mapped_sender_returns_unconverted_value() {
+        let (sender, receiver) = channel::<String>();
+        let mapped = sender.with(|n: u32| n.to_string());
+        drop(receiver);
 
-        sleep(Duration::from_millis(2000));
-        if *finished_flag.lock().unwrap() {
-            panic!("Spawned thread did not block on recv");
-        }
+        assert_eq!(mapped.send(7), Err(SendError(7)));
+    }
 
-        sender.send(DummyPayload::new()).unwrap();
+    #[test]
+    fn test_mapped_sender_returns_value_when_receivers_leave_while_blocked() {
+        let (sender, receiver) = bounded_channel::<String>(1);
+        sender.send(String::from("full")).unwrap();
+        let mapped = sender.with(|n: u32| n.to_string());
 
-        sleep(Duration::from_millis(2000));
-        if !*finished_flag.lock().unwrap() {
-            panic!("Spawned thread did not finish");
-        }
+        let handle = spawn(move || mapped.send(7));
+        sleep(Duration::from_millis(500));
+        drop(receiver);
+
+        assert_eq!(handle.join().unwrap(), Err(SendError(7)));
     }
 
     #[test]
-    fn test_recv_returns_sent_value_threaded() {
-        let payload = DummyPayloadWithValue::new(4123);
+    #[should_panic(expected = "does not support rendezvous channels")]
+    fn test_mapped_sender_rejects_rendezvous_channel() {
+        let (sender, _receiver) = bounded_channel::<String>(0);
+        let _ = sender.with(|n: u32| n.to_string());
+    }
+
+    #[test]
+    fn test_high_water_mark() {
         let (sender, receiver) = channel();
+        assert_eq!(receiver.high_water_mark(), 0);
 
-        spawn(move || {
-            sender.send(payload).unwrap();
-        });
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+        for _ in 0..5 {
+            receiver.recv().unwrap();
+        }
+        assert_eq!(receiver.high_water_mark(), 5);
+
+        // A smaller burst does not lower the mark
+        sender.send(0).unwrap();
+        sender.send(1).unwrap();
+        assert_eq!(receiver.high_water_mark(), 5);
+    }
 
+    #[test]
+    fn test_bounded_channel_wraps_around() {
+        let (sender, receiver) = bounded_channel(3);
         let handle = spawn(move || {
-            sleep(Duration::from_millis(1000));
-            let received = receiver.recv().unwrap();
-            assert_eq!(received, payload);
+            for i in 0..100 {
+                sender.send(DummyPayloadWithValue::new(i)).unwrap();
+            }
         });
 
+        for i in 0..100 {
+            assert_eq!(receiver.recv().unwrap(), DummyPayloadWithValue::new(i));
+        }
         handle.join().unwrap();
+        assert!(receiver.high_water_mark() <= 3);
     }
 
     #[test]
-    fn test_cloning_works() {
-        let target_payload = DummyPayloadWithValue::new(32);
+    fn test_try_iter_drains_available_values() {
         let (sender, receiver) = channel();
-        let sender2 = sender.clone();
+        for i in 0..3 {
+            sender.send(i).unwrap();
+        }
 
-        sender2.send(DummyPayloadWithValue::new(32)).unwrap();
-        assert_eq!(receiver.recv().unwrap(), target_payload);
+        assert_eq!(receiver.try_iter().count(), 3);
+        let mut iter = receiver.try_iter();
+        assert_eq!(iter.next(), None);
+        assert!(!iter.is_disconnected());
+
+        sender.send(3).unwrap();
+        drop(sender);
+        let mut iter = receiver.try_iter();
+        assert_eq!(iter.next(), Some(3));
+        assert_eq!(iter.next(), None);
+        assert!(iter.is_disconnected());
     }
 
     #[test]
-    fn test_no_hang_on_dropped_sender() {
-        let (sender, receiver): (Sender<DummyPayload>, Receiver<DummyPayload>) = channel();
-        let s2 = sender.clone();
-        drop(sender);
+    fn test_wait_empty_returns_after_drain() {
+        let (sender, receiver) = channel();
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
 
-        s2.send(DummyPayload::new()).unwrap();
-        assert!(receiver.recv().is_ok());
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let consumed2 = Arc::clone(&consumed);
+        spawn(move || {
+            for _ in receiver.iter() {
+                sleep(Duration::from_millis(100));
+                consumed2.fetch_add(1, Ordering::Relaxed);
+            }
+        });
 
-        drop(s2);
-        assert!(receiver.recv().is_err());
+        sender.wait_empty().unwrap();
+        // The last value was taken, and is at most being processed
+        assert!(consumed.load(Ordering::Relaxed) >= 4);
+        assert!(sender.is_empty());
     }
 
     #[test]
-    fn test_drop_senders_wakes_receiver() {
-        let (sender, receiver): (Sender<DummyPayload>, _) = channel();
-        let sender2 = sender.clone();
-        let finished = Arc::new(Mutex::new(false));
-        let finished2 = Arc::clone(&finished);
+    fn test_wait_empty_fails_when_receiver_dropped() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
 
-        spawn(move || match receiver.recv() {
-            Ok(_) => panic!("received value when it shouldn't"),
-            Err(msg) => {
-                assert_eq!(msg, "no more values");
-                *finished2.lock().unwrap() = true;
-            }
+        spawn(move || {
+            sleep(Duration::from_millis(200));
+            receiver.recv().unwrap();
+            drop(receiver);
         });
 
-        sleep(Duration::from_millis(1000));
-        assert!(!*finished.lock().unwrap());
+        assert_eq!(sender.wait_empty(), Err(WaitEmptyError));
+        // Nothing in flight, nothing to wait for
+        let (sender, _receiver) = channel::<u32>();
+        assert_eq!(sender.wait_empty(), Ok(()));
+    }
+
+    #[test]
+    fn test_channel_with_capacity() {
+        let (sender, receiver) = channel_with_capacity(64);
+        match &*receiver.inner.shared.lock().unwrap() {
+            Queue::Unbounded(levels) => assert!(levels[0].capacity() >= 64),
+            Queue::Bounded(_) => panic!("channel_with_capacity is unbounded"),
+        }
+
+        // Still unbounded
+        assert_eq!(sender.capacity(), None);
+        for i in 0..100 {
+            sender.try_send(i).unwrap();
+        }
+        assert_eq!(receiver.try_iter().count(), 100);
+    }
+
+    #[test]
+    fn test_is_disconnected() {
+        let (sender, receiver) = channel();
+        assert!(!sender.is_disconnected());
+        assert!(!receiver.is_disconnected());
 
+        sender.send(1).unwrap();
         drop(sender);
-        sleep(Duration::from_millis(1000));
-        assert!(!*finished.lock().unwrap());
+        assert!(receiver.is_disconnected());
+        // Buffered values can still be drained
+        assert_eq!(receiver.recv(), Ok(1));
 
-        drop(sender2);
-        sleep(Duration::from_millis(1000));
-        assert!(*finished.lock().unwrap());
+        let (sender, receiver) = channel::<u32>();
+        drop(receiver);
+        assert!(sender.is_disconnected());
+
+        let (sender, receiver) = channel::<u32>();
+        sender.close();
+        assert!(sender.is_disconnected());
+        assert!(receiver.is_disconnected());
     }
 }
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LockResult, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futer::{Futer, FuterCondvar, FuterGuard};
+
+// Futer does not track poisoning, so this adds it on top the way
+// std::sync::Mutex does: a thread that panics while holding the lock marks it
+// as poisoned, and every later lock reports it.
+pub(crate) struct PoisonFuter<T> {
+    futer: Futer<T>,
+    poisoned: AtomicBool,
+}
+
+impl<T> PoisonFuter<T> {
+    pub(crate) fn new(val: T) -> Self {
+        let futer = Futer::new(val);
+        let poisoned = AtomicBool::new(false);
+        Self { futer, poisoned }
+    }
+
+    pub(crate) fn lock(&self) -> LockResult<PoisonGuard<'_, T>> {
+        let guard = PoisonGuard {
+            guard: self.futer.lock().unwrap(),
+            poisoned: &self.poisoned,
+            poison: !thread::panicking(),
+        };
+        guard.check()
+    }
+}
+
+pub(crate) struct PoisonGuard<'a, T> {
+    guard: FuterGuard<'a, T>,
+    poisoned: &'a AtomicBool,
+    // Only a panic that starts while the lock is held poisons it.
+    poison: bool,
+}
+
+impl<'a, T> PoisonGuard<'a, T> {
+    // Keeps a panic from poisoning the lock, for callers that only read the
+    // value and so cannot leave it half updated.
+    pub(crate) fn read_only(mut self) -> Self {
+        self.poison = false;
+        self
+    }
+
+    fn check(self) -> LockResult<Self> {
+        if self.poisoned.load(Ordering::Relaxed) {
+            Err(PoisonError::new(self))
+        } else {
+            Ok(self)
+        }
+    }
+
+    // Runs f with the lock released, taking it again before returning.
+    pub(crate) fn unlocked<R, F: FnOnce() -> R>(&mut self, f: F) -> R {
+        FuterGuard::unlocked(&mut self.guard, f)
+    }
+
+    // Waits on cvar, with the same poisoning rules as std's Condvar::wait.
+    pub(crate) fn wait(mut self, cvar: &FuterCondvar) -> LockResult<Self> {
+        cvar.wait(&mut self.guard);
+        self.check()
+    }
+
+    pub(crate) fn wait_timeout(
+        mut self,
+        cvar: &FuterCondvar,
+        timeout: Duration,
+    ) -> LockResult<Self> {
+        cvar.wait_timeout(&mut self.guard, timeout);
+        self.check()
+    }
+
+    // Waits until the deadline at the latest, or without a timeout if there
+    // is none.
+    pub(crate) fn wait_deadline(
+        self,
+        cvar: &FuterCondvar,
+        deadline: Option<Instant>,
+    ) -> LockResult<Self> {
+        match deadline {
+            None => self.wait(cvar),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                self.wait_timeout(cvar, timeout)
+            }
+        }
+    }
+}
+
+impl<'a, T> std::ops::Deref for PoisonGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<'a, T> std::ops::DerefMut for PoisonGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<'a, T> Drop for PoisonGuard<'a, T> {
+    fn drop(&mut self) {
+        // Runs before the futer guard field is dropped, so the flag is set
+        // by the time the next thread gets the lock.
+        if self.poison && thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+}
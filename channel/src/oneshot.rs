@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use futer::{Futer, FuterCondvar};
+
+// The state of a oneshot channel. The sender is consumed by send, so the
+// slot is filled at most once, and dropped records whether it went away.
+struct Slot<T> {
+    value: Option<T>,
+    dropped: bool,
+}
+
+struct Inner<T> {
+    slot: Futer<Slot<T>>,
+    cvar: FuterCondvar,
+}
+
+// Sends a single value. Dropping it without sending cancels the channel.
+pub struct OneshotSender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> OneshotSender<T> {
+    // Hands the value over to the receiver, giving it back if the receiver
+    // has already been dropped.
+    pub fn send(self, t: T) -> Result<(), T> {
+        if Arc::strong_count(&self.inner) == 1 {
+            return Err(t);
+        }
+        self.inner.slot.lock().unwrap().value = Some(t);
+        // Dropping self right after marks the slot and wakes the receiver.
+        Ok(())
+    }
+}
+
+impl<T> Drop for OneshotSender<T> {
+    fn drop(&mut self) {
+        self.inner.slot.lock().unwrap().dropped = true;
+        self.inner.cvar.notify_one();
+    }
+}
+
+// Returned by recv when the sender was dropped without sending a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "oneshot sender dropped without sending".fmt(f)
+    }
+}
+
+impl Error for Canceled {}
+
+pub struct OneshotReceiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> OneshotReceiver<T> {
+    // Blocks until the value is sent, or the sender is dropped.
+    pub fn recv(self) -> Result<T, Canceled> {
+        let mut slot = self.inner.slot.lock().unwrap();
+        while !slot.dropped {
+            self.inner.cvar.wait(&mut slot);
+        }
+        slot.value.take().ok_or(Canceled)
+    }
+}
+
+// Creates a channel that carries exactly one value, for request/response
+// style exchanges.
+pub fn channel<T>() -> (OneshotSender<T>, OneshotReceiver<T>) {
+    let slot = Futer::new(Slot {
+        value: None,
+        dropped: false,
+    });
+    let cvar = FuterCondvar::new();
+    let inner = Arc::new(Inner { slot, cvar });
+    (
+        OneshotSender {
+            inner: Arc::clone(&inner),
+        },
+        OneshotReceiver { inner },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn test_oneshot_send_and_recv() {
+        let (sender, receiver) = channel();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            sender.send(42).unwrap();
+        });
+
+        assert_eq!(receiver.recv(), Ok(42));
+    }
+
+    #[test]
+    fn test_oneshot_canceled() {
+        let (sender, receiver) = channel::<u32>();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            drop(sender);
+        });
+
+        assert_eq!(receiver.recv(), Err(Canceled));
+    }
+
+    #[test]
+    fn test_oneshot_send_after_receiver_dropped() {
+        let (sender, receiver) = channel();
+        drop(receiver);
+
+        assert_eq!(sender.send(42), Err(42));
+    }
+}
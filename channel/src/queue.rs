@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use crate::ring::RingBuffer;
+
+// The storage behind a channel. Unbounded channels have one growable FIFO
+// queue per priority level, plain ones only have level 0. Bounded channels
+// have a single level, stored in a ring buffer allocated up front.
+pub(crate) enum Queue<T> {
+    Unbounded(Vec<VecDeque<T>>),
+    Bounded(RingBuffer<T>),
+}
+
+impl<T> Queue<T> {
+    pub(crate) fn new(levels: usize) -> Self {
+        assert!(levels > 0, "a channel needs at least one priority level");
+        let levels = (0..levels).map(|_| VecDeque::new()).collect();
+        Queue::Unbounded(levels)
+    }
+
+    // A single level with room for capacity values before it has to grow.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Queue::Unbounded(vec![VecDeque::with_capacity(capacity)])
+    }
+
+    // A rendezvous channel still needs a slot for the value being handed
+    // over, so a capacity of 0 gets room for one.
+    pub(crate) fn bounded(capacity: usize) -> Self {
+        Queue::Bounded(RingBuffer::new(capacity.max(1)))
+    }
+
+    pub(crate) fn levels(&self) -> usize {
+        match self {
+            Queue::Unbounded(levels) => levels.len(),
+            Queue::Bounded(_) => 1,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Queue::Unbounded(levels) => levels.iter().map(VecDeque::len).sum(),
+            Queue::Bounded(ring) => ring.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            Queue::Unbounded(levels) => levels.iter().all(VecDeque::is_empty),
+            Queue::Bounded(ring) => ring.is_empty(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, level: usize, t: T) {
+        match self {
+            Queue::Unbounded(levels) => levels[level].push_back(t),
+            Queue::Bounded(ring) => ring.push_back(t),
+        }
+    }
+
+    // Pops from the highest non-empty level.
+    pub(crate) fn pop_front(&mut self) -> Option<T> {
+        match self {
+            Queue::Unbounded(levels) => levels.iter_mut().rev().find_map(VecDeque::pop_front),
+            Queue::Bounded(ring) => ring.pop_front(),
+        }
+    }
+
+    // The value pop_front would return next.
+    pub(crate) fn front(&self) -> Option<&T> {
+        match self {
+            Queue::Unbounded(levels) => levels.iter().rev().find_map(VecDeque::front),
+            Queue::Bounded(ring) => ring.front(),
+        }
+    }
+
+    // Takes back the most recently pushed value of a level.
+    pub(crate) fn pop_back(&mut self, level: usize) -> Option<T> {
+        match self {
+            Queue::Unbounded(levels) => levels[level].pop_back(),
+            Queue::Bounded(ring) => ring.pop_back(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_level_is_fifo() {
+        let mut queue = Queue::new(1);
+        for i in 0..3 {
+            queue.push(0, i);
+        }
+
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.pop_front(), Some(0));
+        assert_eq!(queue.pop_back(0), Some(2));
+        assert_eq!(queue.pop_front(), Some(1));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_pops_highest_level_first() {
+        let mut queue = Queue::new(3);
+        queue.push(0, "low");
+        queue.push(2, "high");
+        queue.push(1, "mid");
+        queue.push(2, "high2");
+
+        assert_eq!(queue.front(), Some(&"high"));
+        assert_eq!(queue.pop_front(), Some("high"));
+        assert_eq!(queue.pop_front(), Some("high2"));
+        assert_eq!(queue.pop_front(), Some("mid"));
+        assert_eq!(queue.pop_front(), Some("low"));
+        assert_eq!(queue.pop_front(), None);
+    }
+
+    #[test]
+    fn test_bounded_queue() {
+        let mut queue = Queue::bounded(2);
+        assert_eq!(queue.levels(), 1);
+        queue.push(0, 1);
+        queue.push(0, 2);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.front(), Some(&1));
+        assert_eq!(queue.pop_front(), Some(1));
+        queue.push(0, 3);
+        assert_eq!(queue.pop_back(0), Some(3));
+        assert_eq!(queue.pop_front(), Some(2));
+        assert!(queue.is_empty());
+
+        // Rendezvous channels still get a slot
+        let mut queue = Queue::bounded(0);
+        queue.push(0, 1);
+        assert_eq!(queue.pop_front(), Some(1));
+    }
+}
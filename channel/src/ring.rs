@@ -0,0 +1,143 @@
+use std::mem::MaybeUninit;
+
+// A fixed-size FIFO queue. All the memory is allocated up front, so pushing
+// never reallocates like a VecDeque does when it grows.
+pub(crate) struct RingBuffer<T> {
+    buf: Box<[MaybeUninit<T>]>,
+    // Index of the oldest value.
+    head: usize,
+    len: usize,
+}
+
+impl<T> RingBuffer<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a ring buffer needs room for a value");
+        let buf = (0..capacity).map(|_| MaybeUninit::uninit()).collect();
+        Self {
+            buf,
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn index(&self, offset: usize) -> usize {
+        (self.head + offset) % self.capacity()
+    }
+
+    // Panics if the buffer is full. The channel only pushes once it has
+    // checked there is room.
+    pub(crate) fn push_back(&mut self, t: T) {
+        assert!(self.len < self.capacity(), "pushing to a full ring buffer");
+        let tail = self.index(self.len);
+        self.buf[tail].write(t);
+        self.len += 1;
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        // Safety: the first len slots starting from head are initialized,
+        // and moving head past the slot means it will not be read again.
+        let t = unsafe { self.buf[self.head].assume_init_read() };
+        self.head = self.index(1);
+        self.len -= 1;
+        Some(t)
+    }
+
+    pub(crate) fn pop_back(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        self.len -= 1;
+        let tail = self.index(self.len);
+        // Safety: the slot was the last initialized one, and is now outside
+        // of the first len slots.
+        Some(unsafe { self.buf[tail].assume_init_read() })
+    }
+
+    pub(crate) fn front(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        // Safety: the slot at head is initialized while the buffer is not
+        // empty.
+        Some(unsafe { self.buf[self.head].assume_init_ref() })
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        // MaybeUninit never drops its contents, so the values still queued
+        // have to be dropped by hand.
+        while self.pop_front().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_ring_buffer_wraps_around() {
+        let mut ring = RingBuffer::new(3);
+        for round in 0..10 {
+            ring.push_back(round);
+            ring.push_back(round + 100);
+            assert_eq!(ring.front(), Some(&round));
+            assert_eq!(ring.pop_front(), Some(round));
+            assert_eq!(ring.pop_front(), Some(round + 100));
+        }
+        assert!(ring.is_empty());
+
+        ring.push_back(1);
+        ring.push_back(2);
+        ring.push_back(3);
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.pop_back(), Some(3));
+        assert_eq!(ring.pop_front(), Some(1));
+        assert_eq!(ring.pop_front(), Some(2));
+        assert_eq!(ring.pop_front(), None);
+        assert_eq!(ring.pop_back(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_ring_buffer_push_when_full() {
+        let mut ring = RingBuffer::new(1);
+        ring.push_back(1);
+        ring.push_back(2);
+    }
+
+    #[test]
+    fn test_ring_buffer_drops_remaining_values_once() {
+        let value = Rc::new(());
+        let mut ring = RingBuffer::new(4);
+        // Move head so the remaining values wrap around the end
+        for _ in 0..3 {
+            ring.push_back(Rc::clone(&value));
+            ring.pop_front();
+        }
+        for _ in 0..3 {
+            ring.push_back(Rc::clone(&value));
+        }
+        assert_eq!(Rc::strong_count(&value), 4);
+
+        drop(ring.pop_front());
+        drop(ring);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use futer::{Futer, FuterCondvar};
+
+use crate::Receiver;
+
+// The parking spot shared by all channels a Select waits on. Senders set the
+// flag and notify after every send, so a selecting thread wakes up no matter
+// which of its channels became ready.
+pub(crate) struct Signal {
+    ready: Futer<bool>,
+    cvar: FuterCondvar,
+}
+
+impl Signal {
+    fn new() -> Self {
+        let ready = Futer::new(false);
+        let cvar = FuterCondvar::new();
+        Self { ready, cvar }
+    }
+
+    pub(crate) fn notify(&self) {
+        *self.ready.lock().unwrap() = true;
+        self.cvar.notify_all();
+    }
+}
+
+// Lets Select hold receivers of different payload types.
+trait Selectable {
+    fn is_ready(&self) -> bool;
+    fn register(&self, signal: &Arc<Signal>);
+    fn unregister(&self, signal: &Arc<Signal>);
+}
+
+impl<T> Selectable for Receiver<T> {
+    // A receiver is ready when recv would not block: either a value is
+    // queued, or all senders are gone or the lock is poisoned and recv would
+    // return an error.
+    fn is_ready(&self) -> bool {
+        match self.inner.shared.lock() {
+            Ok(que) => !que.is_empty() || self.inner.no_senders(),
+            Err(_) => true,
+        }
+    }
+
+    fn register(&self, signal: &Arc<Signal>) {
+        self.inner.register_selector(signal);
+    }
+
+    fn unregister(&self, signal: &Arc<Signal>) {
+        self.inner.unregister_selector(signal);
+    }
+}
+
+// Waits on several receivers at once, returning the index of the first one
+// that is ready to be received from.
+#[derive(Default)]
+pub struct Select<'a> {
+    receivers: Vec<&'a dyn Selectable>,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Self {
+        Self { receivers: Vec::new() }
+    }
+
+    // Adds a receiver to the set, returning the index wait reports for it.
+    pub fn add<T>(&mut self, receiver: &'a Receiver<T>) -> usize {
+        self.receivers.push(receiver);
+        self.receivers.len() - 1
+    }
+
+    // Blocks until one of the receivers has a value, or has been disconnected,
+    // and returns its index. The value itself is left in the channel, to be
+    // taken with recv or try_recv.
+    //
+    // If several receivers are ready, the one added first wins.
+    pub fn wait(&self) -> usize {
+        assert!(!self.receivers.is_empty(), "waiting on an empty Select");
+
+        let signal = Arc::new(Signal::new());
+        for receiver in &self.receivers {
+            receiver.register(&signal);
+        }
+
+        let index = loop {
+            // Clearing the flag before checking the receivers means a send
+            // that lands after the check will set it again, so we cannot
+            // sleep through it.
+            *signal.ready.lock().unwrap() = false;
+            if let Some(index) = self.receivers.iter().position(|r| r.is_ready()) {
+                break index;
+            }
+
+            let mut ready = signal.ready.lock().unwrap();
+            while !*ready {
+                signal.cvar.wait(&mut ready);
+            }
+        };
+
+        for receiver in &self.receivers {
+            receiver.unregister(&signal);
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel;
+    use std::sync::atomic::Ordering;
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn test_select_returns_ready_receiver() {
+        let (_sender1, receiver1) = channel::<u32>();
+        let (sender2, receiver2) = channel::<String>();
+
+        sender2.send(String::from("hello")).unwrap();
+
+        let mut select = Select::new();
+        assert_eq!(select.add(&receiver1), 0);
+        assert_eq!(select.add(&receiver2), 1);
+
+        assert_eq!(select.wait(), 1);
+        assert_eq!(receiver2.recv().unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_select_blocks_until_send() {
+        let (_sender1, receiver1) = channel::<u32>();
+        let (sender2, receiver2) = channel::<u32>();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            sender2.send(42).unwrap();
+            // Keep the sender alive so the wakeup is due to the send
+            sleep(Duration::from_millis(1000));
+        });
+
+        let mut select = Select::new();
+        select.add(&receiver1);
+        select.add(&receiver2);
+
+        assert_eq!(select.wait(), 1);
+        assert_eq!(receiver2.try_recv(), Ok(42));
+        assert!(receiver2.inner.selectors.lock().unwrap().is_empty());
+        assert_eq!(receiver2.inner.num_selectors.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_select_wakes_on_disconnect() {
+        let (_sender1, receiver1) = channel::<u32>();
+        let (sender2, receiver2) = channel::<u32>();
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            drop(sender2);
+        });
+
+        let mut select = Select::new();
+        select.add(&receiver1);
+        select.add(&receiver2);
+
+        assert_eq!(select.wait(), 1);
+        assert!(receiver2.recv().is_err());
+    }
+}
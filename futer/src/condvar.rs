@@ -0,0 +1,373 @@
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use futex_ffi::futex_requeue;
+use futex_ffi::{futex_wait, futex_wake_all, futex_wake_one, FutexError, FutexFlags};
+
+use crate::{acquire, release, FuterGuard, RealFutexCalls, DEFAULT_SPIN};
+
+/// A condition variable to be used together with a `Futer`.
+///
+/// The futex word is a sequence number that every notification bumps. A
+/// waiter reads it while still holding the lock, and the kernel only puts it
+/// to sleep if the word has not changed since, so a notification sent
+/// between unlocking and sleeping is never lost.
+///
+/// Notifying only makes a syscall when some thread is actually waiting, so
+/// signaling a condvar nobody waits on costs a couple of atomic operations.
+///
+/// On Linux, `notify_all` wakes a single waiter and moves the others over to
+/// wait on the futer, so they are handed the lock one at a time instead of
+/// all waking up just to fight over it. This is why all threads waiting at
+/// the same time must use the same futer: waiting with a guard of another one
+/// while others wait panics. Once nobody waits, the condvar can be used with
+/// any futer.
+///
+/// Like any condition variable it can wake up spuriously, so waits should
+/// be done in a loop that re-checks the condition.
+#[derive(Debug, Default)]
+pub struct FuterCondvar {
+    seq: AtomicU32,
+    // Threads between registering in wait and returning from it.
+    waiters: AtomicU32,
+    // The word of the futer the waiters relock, or null when nobody waits.
+    futer: AtomicPtr<AtomicU32>,
+    // A lock word for updating waiters and futer together. notify_all holds
+    // it while it requeues, so the last waiter cannot unregister, return and
+    // free its futer before the requeue is done with it.
+    state: AtomicU32,
+}
+
+impl FuterCondvar {
+    pub fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            futer: AtomicPtr::new(ptr::null_mut()),
+            state: AtomicU32::new(0),
+        }
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce() -> R) -> R {
+        acquire::<RealFutexCalls, DEFAULT_SPIN>(&self.state);
+        let res = f();
+        release::<RealFutexCalls>(&self.state);
+        res
+    }
+
+    // Registers a waiter while the lock is still held. A notifier changes
+    // the condition under the lock before notifying, so if it sees no
+    // waiters, any thread that waits later is going to see the new
+    // condition, or a new seq, and not sleep through the notification.
+    fn register<T, const SPIN: usize>(&self, guard: &FuterGuard<'_, T, SPIN>) -> u32 {
+        let word = FuterGuard::lock_word(guard) as *const AtomicU32 as *mut AtomicU32;
+        let same_futer = self.with_state(|| {
+            let futer = self.futer.load(Ordering::Relaxed);
+            if !futer.is_null() && futer != word {
+                return false;
+            }
+            self.futer.store(word, Ordering::Relaxed);
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+            true
+        });
+        // Panicking only once the state lock is released
+        assert!(
+            same_futer,
+            "a FuterCondvar was used with two different futers at once"
+        );
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    fn unregister(&self) {
+        self.with_state(|| {
+            // The futer may go away as soon as its last waiter returns
+            if self.waiters.fetch_sub(1, Ordering::SeqCst) == 1 {
+                self.futer.store(ptr::null_mut(), Ordering::Relaxed);
+            }
+        });
+    }
+
+    // Bumps seq and returns whether anybody has to be woken up.
+    fn bump(&self) -> bool {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        self.waiters.load(Ordering::SeqCst) != 0
+    }
+
+    /// Unlocks the futer of `guard` and blocks until notified, locking it
+    /// again before returning.
+    pub fn wait<T, const SPIN: usize>(&self, guard: &mut FuterGuard<'_, T, SPIN>) {
+        let seq = self.register(guard);
+        // Errors only mean seq already changed, or a signal interrupted us,
+        // and callers have to deal with spurious wakeups anyway. notify_all
+        // may have requeued waiters onto the futer behind us, so we have to
+        // relock as contested for our unlock to wake them.
+        let _ = FuterGuard::unlocked_contested(guard, || {
+            futex_wait(&self.seq, seq, None, FutexFlags::PRIVATE)
+        });
+        self.unregister();
+    }
+
+    /// Like `wait`, but gives up after `timeout`. Returns true if the
+    /// timeout elapsed.
+    pub fn wait_timeout<T, const SPIN: usize>(
+        &self,
+        guard: &mut FuterGuard<'_, T, SPIN>,
+        timeout: Duration,
+    ) -> bool {
+        let seq = self.register(guard);
+        let res = FuterGuard::unlocked_contested(guard, || {
+            futex_wait(&self.seq, seq, Some(&timeout.into()), FutexFlags::PRIVATE)
+        });
+        self.unregister();
+        matches!(res, Err(FutexError::TimedOut))
+    }
+
+    /// Wakes up one thread blocked on this condvar.
+    pub fn notify_one(&self) {
+        if self.bump() {
+            let _ = futex_wake_one(&self.seq, FutexFlags::PRIVATE);
+        }
+    }
+
+    /// Wakes up every thread blocked on this condvar.
+    pub fn notify_all(&self) {
+        if !self.bump() {
+            return;
+        }
+        // The woken waiter relocks as contested, so its unlock wakes one of
+        // the requeued ones, which relocks as contested in turn. A waiter
+        // that has not gone to sleep yet sees the new seq anyway.
+        #[cfg(target_os = "linux")]
+        let requeued = self.with_state(|| {
+            let futer = self.futer.load(Ordering::Relaxed);
+            // Everybody stopped waiting since bump looked
+            if futer.is_null() {
+                return true;
+            }
+            // Safety: futer is only set while some thread is registered, and
+            // that thread cannot unregister while we hold the state lock. It
+            // holds a guard of the futer until then, so the futer is alive.
+            let futer = unsafe { &*futer };
+            futex_requeue(&self.seq, futer, 1, i32::MAX as u32, FutexFlags::PRIVATE).is_ok()
+        });
+        #[cfg(target_os = "linux")]
+        if requeued {
+            return;
+        }
+        let _ = futex_wake_all(&self.seq, FutexFlags::PRIVATE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Futer;
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+    use std::time::Instant;
+
+    #[test]
+    fn condvar_wait_until_notified() {
+        let pair = Arc::new((Futer::new(false), FuterCondvar::new()));
+        let pair2 = Arc::clone(&pair);
+
+        spawn(move || {
+            sleep(Duration::from_millis(500));
+            let (futer, condvar) = &*pair2;
+            *futer.lock().unwrap() = true;
+            condvar.notify_one();
+        });
+
+        let (futer, condvar) = &*pair;
+        let mut ready = futer.lock().unwrap();
+        while !*ready {
+            condvar.wait(&mut ready);
+        }
+        assert!(*ready);
+    }
+
+    #[test]
+    fn condvar_notify_all_wakes_every_waiter() {
+        const NUM_THREADS: usize = 4;
+
+        let pair = Arc::new((Futer::new(0), FuterCondvar::new()));
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let pair = Arc::clone(&pair);
+                spawn(move || {
+                    let (futer, condvar) = &*pair;
+                    let mut generation = futer.lock().unwrap();
+                    while *generation == 0 {
+                        condvar.wait(&mut generation);
+                    }
+                })
+            })
+            .collect();
+
+        sleep(Duration::from_millis(500));
+        let (futer, condvar) = &*pair;
+        *futer.lock().unwrap() = 1;
+        condvar.notify_all();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn condvar_notify_all_hands_the_lock_to_every_waiter() {
+        const NUM_THREADS: usize = 8;
+        const NUM_ROUNDS: usize = 50;
+
+        #[derive(Default)]
+        struct Rounds {
+            started: usize,
+            arrived: usize,
+        }
+
+        // Each waiter has to get the lock once per round to count itself in,
+        // which requeued waiters only do if the lock is passed on to them.
+        let pair = Arc::new((Futer::new(Rounds::default()), FuterCondvar::new()));
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let pair = Arc::clone(&pair);
+                spawn(move || {
+                    let (futer, condvar) = &*pair;
+                    for round in 1..=NUM_ROUNDS {
+                        let mut state = futer.lock().unwrap();
+                        while state.started < round {
+                            condvar.wait(&mut state);
+                        }
+                        state.arrived += 1;
+                    }
+                })
+            })
+            .collect();
+
+        let (futer, condvar) = &*pair;
+        for round in 1..=NUM_ROUNDS {
+            loop {
+                let mut state = futer.lock().unwrap();
+                if state.arrived == (round - 1) * NUM_THREADS {
+                    state.started = round;
+                    break;
+                }
+            }
+            condvar.notify_all();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(futer.lock().unwrap().arrived, NUM_THREADS * NUM_ROUNDS);
+    }
+
+    #[test]
+    #[should_panic(expected = "two different futers")]
+    fn condvar_rejects_a_second_futer_while_waited_on() {
+        let pair = Arc::new((Futer::new(()), FuterCondvar::new()));
+        let pair2 = Arc::clone(&pair);
+        spawn(move || {
+            let (futer, condvar) = &*pair2;
+            let mut guard = futer.lock().unwrap();
+            condvar.wait_timeout(&mut guard, Duration::from_secs(5));
+        });
+
+        sleep(Duration::from_millis(500));
+        let other = Futer::new(());
+        let mut guard = other.lock().unwrap();
+        pair.1.wait_timeout(&mut guard, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn condvar_can_move_between_waits() {
+        let pair = (Futer::new(()), FuterCondvar::new());
+        {
+            let mut guard = pair.0.lock().unwrap();
+            pair.1.wait_timeout(&mut guard, Duration::from_millis(1));
+        }
+        assert!(pair.1.futer.load(Ordering::Relaxed).is_null());
+
+        // Moving the pair, or pairing the condvar with a new futer, is fine
+        // once nobody waits anymore
+        let moved = Box::new(pair);
+        {
+            let mut guard = moved.0.lock().unwrap();
+            moved.1.wait_timeout(&mut guard, Duration::from_millis(1));
+        }
+        let (_, condvar) = *moved;
+        let futer = Futer::new(());
+        let mut guard = futer.lock().unwrap();
+        condvar.wait_timeout(&mut guard, Duration::from_millis(1));
+        condvar.notify_all();
+    }
+
+    #[test]
+    fn condvar_wait_timeout_expires() {
+        let futer = Futer::new(());
+        let condvar = FuterCondvar::new();
+
+        let mut guard = futer.lock().unwrap();
+        let start = Instant::now();
+        assert!(condvar.wait_timeout(&mut guard, Duration::from_millis(200)));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        // The lock is held again once the wait returns
+        assert!(futer.try_lock().is_err());
+        assert_eq!(condvar.waiters.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn condvar_counts_waiters() {
+        let pair = Arc::new((Futer::new(false), FuterCondvar::new()));
+        let pair2 = Arc::clone(&pair);
+
+        let handle = spawn(move || {
+            let (futer, condvar) = &*pair2;
+            let mut ready = futer.lock().unwrap();
+            while !*ready {
+                condvar.wait(&mut ready);
+            }
+        });
+
+        sleep(Duration::from_millis(500));
+        let (futer, condvar) = &*pair;
+        assert_eq!(condvar.waiters.load(Ordering::Relaxed), 1);
+        *futer.lock().unwrap() = true;
+        condvar.notify_one();
+        handle.join().unwrap();
+        assert_eq!(condvar.waiters.load(Ordering::Relaxed), 0);
+    }
+}
+
+#[cfg(all(test, not(feature = "stable")))]
+mod benches {
+    use super::*;
+    use test::Bencher;
+
+    const NUM_ITER: usize = 1000;
+
+    // A sender notifying a receiver that keeps up, so nobody is parked.
+    #[bench]
+    fn notify_one_without_waiters(b: &mut Bencher) {
+        let condvar = FuterCondvar::new();
+        b.iter(|| {
+            for _ in 0..NUM_ITER {
+                condvar.notify_one();
+            }
+        });
+    }
+
+    // The same, issuing the wake syscall unconditionally as notify_one used
+    // to do.
+    #[bench]
+    fn notify_one_always_wakes(b: &mut Bencher) {
+        let condvar = FuterCondvar::new();
+        b.iter(|| {
+            for _ in 0..NUM_ITER {
+                condvar.seq.fetch_add(1, Ordering::SeqCst);
+                let _ = futex_wake_one(&condvar.seq, FutexFlags::PRIVATE);
+            }
+        });
+    }
+}
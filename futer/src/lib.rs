@@ -1,26 +1,48 @@
-#![feature(dropck_eyepatch)]
-#![feature(test)]
+// Both features are only needed for #[may_dangle] on the guard and for the
+// benchmarks, see the stable feature in Cargo.toml.
+#![cfg_attr(not(feature = "stable"), feature(dropck_eyepatch))]
+#![cfg_attr(not(feature = "stable"), feature(test))]
 
+#[cfg(not(feature = "stable"))]
 extern crate test;
 
+mod condvar;
+// Priority inheritance is a Linux futex feature
+#[cfg(target_os = "linux")]
+mod pi;
+
+pub use condvar::FuterCondvar;
+pub use futex_ffi::FutexTimeout;
+#[cfg(target_os = "linux")]
+pub use pi::{FuterPI, FuterPIGuard};
+
+use std::fmt;
+use std::io;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 use std::marker::PhantomData;
 
-use futex_ffi::{futex_wait, futex_wake, FutexTimeout};
+use futex_ffi::{futex_wait, futex_wake_all, futex_wake_one, FutexError, FutexFlags};
 
 trait Futex {
-    fn futex_wake(lock: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64;
-    fn futex_wait(lock: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64;
+    fn futex_wake_one(lock: &AtomicU32) -> io::Result<usize>;
+    fn futex_wake_all(lock: &AtomicU32) -> io::Result<usize>;
+    fn futex_wait(lock: &AtomicU32, val: u32, timeout: Option<&FutexTimeout>) -> Result<i64, FutexError>;
 }
 
 #[derive(Debug)]
 struct RealFutexCalls;
+// Futers are never shared between processes, so the private futex ops are
+// always enough.
 impl Futex for RealFutexCalls {
-    fn futex_wake(lock: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64 {
-        futex_wake(lock, val, timeout)
+    fn futex_wake_one(lock: &AtomicU32) -> io::Result<usize> {
+        futex_wake_one(lock, FutexFlags::PRIVATE)
     }
-    fn futex_wait(lock: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64 {
-        futex_wait(lock, val, timeout)
+    fn futex_wake_all(lock: &AtomicU32) -> io::Result<usize> {
+        futex_wake_all(lock, FutexFlags::PRIVATE)
+    }
+    fn futex_wait(lock: &AtomicU32, val: u32, timeout: Option<&FutexTimeout>) -> Result<i64, FutexError> {
+        futex_wait(lock, val, timeout, FutexFlags::PRIVATE)
     }
 }
 
@@ -28,20 +50,107 @@ const UNLOCKED: u32 = 0;
 const LOCKED: u32 = 1;
 const CONTESTED: u32 = 2;
 
+/// Number of times a contended lock polls the word before parking in the
+/// kernel. Futer has always parked straight away, so that stays the default.
+pub const DEFAULT_SPIN: usize = 0;
+
+fn acquire<F: Futex, const SPIN: usize>(lock: &AtomicU32) {
+    if let Err(val) = lock.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Acquire) {
+        let mut c = val;
+        // Only spin while the lock is held without waiters: if the word is
+        // CONTESTED, others are already parked and we would just be queueing
+        // behind them anyway.
+        for _ in 0..SPIN {
+            if c == UNLOCKED {
+                match lock.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed) {
+                    Ok(_) => return,
+                    Err(val) => c = val,
+                }
+            }
+            if c == CONTESTED {
+                break;
+            }
+            std::hint::spin_loop();
+            c = lock.load(Ordering::Relaxed);
+        }
+        loop {
+            if (c == 2) || (lock.compare_exchange(LOCKED, CONTESTED, Ordering::Acquire, Ordering::Acquire) == Err(2))  {
+                // The word changing before we slept, or a signal, just means
+                // we have to look at the word again. Without a timeout,
+                // anything else is a bug in how the word is passed.
+                match F::futex_wait(lock, CONTESTED, None) {
+                    Ok(_) | Err(FutexError::WouldBlock) | Err(FutexError::Interrupted) => {}
+                    Err(err) => panic!("futex wait on a futer failed: {}", err),
+                }
+            }
+            c = match lock.compare_exchange(UNLOCKED, CONTESTED, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(val) => val,
+            }
+        }
+    }
+}
+
+// Takes the lock leaving the word CONTESTED, whether or not anybody else
+// waits. A condvar waiter relocks this way because notify_all may have
+// requeued other waiters onto the word behind it, and only a CONTESTED
+// unlock wakes them.
+fn acquire_contested<F: Futex>(lock: &AtomicU32) {
+    while lock.swap(CONTESTED, Ordering::Acquire) != UNLOCKED {
+        match F::futex_wait(lock, CONTESTED, None) {
+            Ok(_) | Err(FutexError::WouldBlock) | Err(FutexError::Interrupted) => {}
+            Err(err) => panic!("futex wait on a futer failed: {}", err),
+        }
+    }
+}
+
+fn release<F: Futex>(lock: &AtomicU32) {
+    // Swapping straight to UNLOCKED, instead of decrementing and then storing,
+    // means the word never reads as held once its owner has let go of it.
+    // Otherwise a try_lock racing with a contested unlock could observe the
+    // intermediate LOCKED value and fail even though nobody owns the lock.
+    if lock.swap(UNLOCKED, Ordering::Release) == CONTESTED {
+        // One is enough: the waiter takes the lock as CONTESTED, so its own
+        // unlock wakes the next one.
+        let _ = F::futex_wake_one(lock);
+    }
+}
+
 #[derive(Debug)]
-struct FuterGuardInternal<'a, T, F: Futex> {
+struct FuterGuardInternal<'a, T, F: Futex, const SPIN: usize = DEFAULT_SPIN> {
     ptr: *const T,
     lock: &'a AtomicU32,
     _futex: PhantomData<fn() -> F>,
 }
 
-impl<'a, T, F: Futex> FuterGuardInternal<'a, T, F> {
+impl<'a, T, F: Futex, const SPIN: usize> FuterGuardInternal<'a, T, F, SPIN> {
     fn new(ptr: *const T, lock: &'a AtomicU32) -> Self {
         Self { ptr, lock, _futex: PhantomData }
     }
+
+    // With contested set, the lock is re-acquired through acquire_contested.
+    fn unlocked<R, U: FnOnce() -> R>(&mut self, contested: bool, f: U) -> R {
+        // Re-acquires the lock when dropped, so the guard holds it again by
+        // the time we return, even if f unwinds.
+        struct Relock<'b, F: Futex, const SPIN: usize>(&'b AtomicU32, bool, PhantomData<fn() -> F>);
+
+        impl<'b, F: Futex, const SPIN: usize> Drop for Relock<'b, F, SPIN> {
+            fn drop(&mut self) {
+                if self.1 {
+                    acquire_contested::<F>(self.0);
+                } else {
+                    acquire::<F, SPIN>(self.0);
+                }
+            }
+        }
+
+        release::<F>(self.lock);
+        let _relock = Relock::<F, SPIN>(self.lock, contested, PhantomData);
+        f()
+    }
 }
 
-impl<'a, T, F: Futex> std::ops::Deref for FuterGuardInternal<'a, T, F> {
+impl<'a, T, F: Futex, const SPIN: usize> std::ops::Deref for FuterGuardInternal<'a, T, F, SPIN> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -53,7 +162,7 @@ impl<'a, T, F: Futex> std::ops::Deref for FuterGuardInternal<'a, T, F> {
     }
 }
 
-impl<'a, T, F: Futex> std::ops::DerefMut for FuterGuardInternal<'a, T, F> {
+impl<'a, T, F: Futex, const SPIN: usize> std::ops::DerefMut for FuterGuardInternal<'a, T, F, SPIN> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         let ptr_mut = self.ptr as *mut T;
         // Safety: Since self exists, we have gained access to the lock, and we can
@@ -62,13 +171,31 @@ impl<'a, T, F: Futex> std::ops::DerefMut for FuterGuardInternal<'a, T, F> {
     }
 }
 
+impl<'a, T: fmt::Display, F: Futex, const SPIN: usize> fmt::Display
+    for FuterGuardInternal<'a, T, F, SPIN>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
 // Safety: T is never accessed in drop, so it is safe to let it dangle
-unsafe impl<'a, #[may_dangle] T, #[may_dangle] F: Futex> Drop for FuterGuardInternal<'a, T, F> {
+#[cfg(not(feature = "stable"))]
+unsafe impl<'a, #[may_dangle] T, #[may_dangle] F: Futex, const SPIN: usize> Drop
+    for FuterGuardInternal<'a, T, F, SPIN>
+{
     fn drop(&mut self) {
-        if self.lock.fetch_sub(1, Ordering::Release) != 1 {
-            self.lock.store(0, Ordering::Release);
-            F::futex_wake(self.lock, u32::MAX, None);
-        }
+        release::<F>(self.lock);
+    }
+}
+
+// Without may_dangle, dropck has to assume the drop uses T, so whatever T
+// borrows has to outlive the guard. Storing a borrow of a value declared
+// after the guard is rejected, even though the guard never looks at it.
+#[cfg(feature = "stable")]
+impl<'a, T, F: Futex, const SPIN: usize> Drop for FuterGuardInternal<'a, T, F, SPIN> {
+    fn drop(&mut self) {
+        release::<F>(self.lock);
     }
 }
 
@@ -77,49 +204,36 @@ pub enum TryLockError {
     WouldBlock,
 }
 
-struct FuterInternal<T, F: Futex> {
+struct FuterInternal<T, F: Futex, const SPIN: usize = DEFAULT_SPIN> {
     val: Box<T>,
     lock: Box<AtomicU32>,
     _futex: PhantomData<fn() -> F>,
 }
 
-impl<T, F: Futex> FuterInternal<T, F> {
+impl<T, F: Futex, const SPIN: usize> FuterInternal<T, F, SPIN> {
     fn new(unboxed_val: T) -> Self {
+        Self::with_state(unboxed_val, UNLOCKED)
+    }
+
+    fn with_state(unboxed_val: T, state: u32) -> Self {
         let val = Box::new(unboxed_val);
-        let lock = Box::new(AtomicU32::new(UNLOCKED));
+        let lock = Box::new(AtomicU32::new(state));
         Self { val, lock, _futex: PhantomData }
     }
 
-    fn lock(&self) -> Result<FuterGuardInternal<T, F>, ()> {
-        match self
-            .lock
-            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Acquire) {
-                Ok(_) => {
-                    return Ok(FuterGuardInternal::new(
-                        self.val.as_ref() as *const T,
-                        self.lock.as_ref(),
-                    ))
-                }
-                Err(val) => {
-                    let mut c = val;
-                    loop {
-                        if (c == 2) || (self.lock.compare_exchange(LOCKED, CONTESTED, Ordering::Acquire, Ordering::Acquire) == Err(2))  {
-                            F::futex_wait(&self.lock, CONTESTED, None);
-                        }
-                        c = match self.lock.compare_exchange(UNLOCKED, CONTESTED, Ordering::Acquire, Ordering::Acquire) {
-                            Ok(_) => break Ok(FuterGuardInternal::new(
-                                    self.val.as_ref() as *const T,
-                                    self.lock.as_ref(),
-                                )),
-                            Err(val) => val,
-                        }
-                    }
-                }
-            }
+    fn lock(&self) -> Result<FuterGuardInternal<'_, T, F, SPIN>, ()> {
+        acquire::<F, SPIN>(&self.lock);
+        Ok(FuterGuardInternal::new(
+            self.val.as_ref() as *const T,
+            self.lock.as_ref(),
+        ))
     }
 
-    fn try_lock(&self) -> Result<FuterGuardInternal<T, F>, TryLockError> {
-        match self.lock.compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Acquire) {
+    fn try_lock(&self) -> Result<FuterGuardInternal<'_, T, F, SPIN>, TryLockError> {
+        // Both LOCKED and CONTESTED mean some guard is live, so UNLOCKED is the
+        // only state we can take the lock from. The strong exchange matters:
+        // a weak one may fail spuriously and report WouldBlock for a free lock.
+        match self.lock.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Acquire) {
             Ok(_) =>
                 Ok(FuterGuardInternal::new(
                     self.val.as_ref() as *const T,
@@ -129,12 +243,30 @@ impl<T, F: Futex> FuterInternal<T, F> {
         }
     }
 
-    fn unlock(guard: FuterGuardInternal<T, F>) {
+    fn unlock(guard: FuterGuardInternal<T, F, SPIN>) {
         drop(guard)
     }
+
+    unsafe fn force_unlock(&self) {
+        // Waking everyone is required here: we do not know whether the word
+        // was CONTESTED, and any waiter we leave parked would never be woken.
+        self.lock.store(UNLOCKED, Ordering::Release);
+        let _ = F::futex_wake_all(&self.lock);
+    }
 }
 
-pub struct Futer<T>(FuterInternal<T, RealFutexCalls>);
+/// A mutual exclusion lock built directly on futexes.
+///
+/// SPIN is the number of times lock polls a held futer before parking the
+/// thread. It is part of the type, so picking it has no runtime cost.
+pub struct Futer<T, const SPIN: usize = DEFAULT_SPIN>(FuterInternal<T, RealFutexCalls, SPIN>);
+
+// Safety: the futer owns its value, so moving it to another thread moves the
+// T along with it, which requires T: Send. Sharing a &Futer<T> only ever
+// hands out the T to one thread at a time through the lock, which is the same
+// as sending it between them, so T: Send is also sufficient for Sync.
+unsafe impl<T: Send, const SPIN: usize> Send for Futer<T, SPIN> {}
+unsafe impl<T: Send, const SPIN: usize> Sync for Futer<T, SPIN> {}
 
 impl<T> Futer<T> {
     #[inline]
@@ -142,38 +274,194 @@ impl<T> Futer<T> {
         Futer(FuterInternal::new(val))
     }
 
+    /// Creates a futer that is already locked, along with the guard for it.
+    ///
+    /// The futer can be published to other threads while the guard is held,
+    /// and they will block until setup completes and the guard is dropped.
+    pub fn new_locked(val: T) -> (Arc<Self>, OwnedFuterGuard<T>) {
+        let futer = Arc::new(Futer(FuterInternal::with_state(val, LOCKED)));
+        // Safety: val and lock live in their own boxes, so their addresses do
+        // not change when the futer is moved into the Arc. The owned guard
+        // keeps a clone of the Arc, and drops its borrow before that clone,
+        // so the 'static references never outlive the memory they point to.
+        let (ptr, lock) = unsafe {
+            (
+                futer.0.val.as_ref() as *const T,
+                &*(futer.0.lock.as_ref() as *const AtomicU32),
+            )
+        };
+        let guard = OwnedFuterGuard {
+            guard: FuterGuard(FuterGuardInternal::new(ptr, lock)),
+            _futer: Arc::clone(&futer),
+        };
+        (futer, guard)
+    }
+}
+
+impl<T, const SPIN: usize> Futer<T, SPIN> {
+    /// Creates a futer that spins `SPIN` times before parking, e.g.
+    /// `Futer::<_, 100>::with_spin(val)`.
+    #[inline]
+    pub fn with_spin(val: T) -> Self {
+        Futer(FuterInternal::new(val))
+    }
+
     #[inline]
-    pub fn lock(&self) -> Result<FuterGuard<T>, ()> {
+    #[allow(clippy::result_unit_err)]
+    pub fn lock(&self) -> Result<FuterGuard<'_, T, SPIN>, ()> {
         self.0.lock().map(|guard| FuterGuard(guard))
     }
 
     #[inline]
-    pub fn try_lock(&self) -> Result<FuterGuard<T>, TryLockError> {
+    pub fn try_lock(&self) -> Result<FuterGuard<'_, T, SPIN>, TryLockError> {
         self.0.try_lock().map(|guard| FuterGuard(guard))
     }
 
     #[inline]
-    pub fn unlock(guard: FuterGuard<T>) {
+    pub fn unlock(guard: FuterGuard<T, SPIN>) {
         FuterInternal::unlock(guard.0)
     }
+
+    /// Releases the lock without a guard, waking every thread parked on it.
+    ///
+    /// This is an escape hatch for a guard that was leaked with
+    /// `mem::forget`, which otherwise keeps the futer locked forever.
+    ///
+    /// # Safety
+    ///
+    /// No `FuterGuard` for this futer may be live. Calling this while a
+    /// guard exists lets a second thread lock the futer and alias the `&mut T`
+    /// handed out by the first guard.
+    #[inline]
+    pub unsafe fn force_unlock(&self) {
+        self.0.force_unlock()
+    }
+
+    /// Runs `f` with the futer locked, returning what it returns.
+    ///
+    /// The guard lives only for the duration of the closure, so the lock is
+    /// released both on return and while unwinding out of a panic in `f`.
+    pub fn with_lock<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        let mut guard = self.lock().unwrap();
+        f(&mut guard)
+    }
+}
+
+/// Locks every futer in `futers`, returning the guards in the same order.
+///
+/// The futers are always acquired in order of address, regardless of their
+/// order in the slice, so two callers locking the same set can never deadlock
+/// against each other.
+///
+/// # Panics
+///
+/// Panics if the same futer appears more than once, since that would
+/// deadlock on itself.
+pub fn lock_many<'a, T, const SPIN: usize>(
+    futers: &[&'a Futer<T, SPIN>],
+) -> Vec<FuterGuard<'a, T, SPIN>> {
+    let addr = |i: &usize| futers[*i].0.lock.as_ref() as *const AtomicU32 as usize;
+
+    let mut order: Vec<usize> = (0..futers.len()).collect();
+    order.sort_by_key(addr);
+    assert!(
+        order.windows(2).all(|w| addr(&w[0]) != addr(&w[1])),
+        "lock_many called with the same futer twice"
+    );
+
+    let mut guards: Vec<Option<FuterGuard<'a, T, SPIN>>> = futers.iter().map(|_| None).collect();
+    for i in order {
+        guards[i] = Some(futers[i].lock().unwrap());
+    }
+    guards.into_iter().map(Option::unwrap).collect()
 }
 
 #[derive(Debug)]
-pub struct FuterGuard<'a, T>(FuterGuardInternal<'a, T, RealFutexCalls>);
+pub struct FuterGuard<'a, T, const SPIN: usize = DEFAULT_SPIN>(
+    FuterGuardInternal<'a, T, RealFutexCalls, SPIN>,
+);
+
+// Safety: the guard is a unique borrow of the protected value, so it follows
+// the rules of &mut T: sending it hands the T to another thread, which needs
+// T: Send, and sharing it hands out &T to several threads, which needs T: Sync.
+// Unlocking is a plain atomic operation plus a wake, so dropping the guard on
+// another thread than the one that locked is fine.
+unsafe impl<'a, T: Send, const SPIN: usize> Send for FuterGuard<'a, T, SPIN> {}
+unsafe impl<'a, T: Sync, const SPIN: usize> Sync for FuterGuard<'a, T, SPIN> {}
+
+impl<'a, T, const SPIN: usize> FuterGuard<'a, T, SPIN> {
+    /// Temporarily releases the lock while running `f`, re-acquiring it
+    /// before returning.
+    ///
+    /// Other threads may lock the futer while `f` runs. The guard keeps
+    /// pointing to the same value once the lock is re-acquired.
+    #[inline]
+    pub fn unlocked<R, F: FnOnce() -> R>(guard: &mut Self, f: F) -> R {
+        guard.0.unlocked(false, f)
+    }
+
+    // Like unlocked, but relocks into the CONTESTED state, for condvar waits.
+    pub(crate) fn unlocked_contested<R, F: FnOnce() -> R>(guard: &mut Self, f: F) -> R {
+        guard.0.unlocked(true, f)
+    }
+
+    // The word of the futer this guard locks.
+    pub(crate) fn lock_word(guard: &Self) -> &'a AtomicU32 {
+        guard.0.lock
+    }
+}
+
+impl<'a, T, const SPIN: usize> std::ops::Deref for FuterGuard<'a, T, SPIN> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a, T, const SPIN: usize> std::ops::DerefMut for FuterGuard<'a, T, SPIN> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'a, T: fmt::Display, const SPIN: usize> fmt::Display for FuterGuard<'a, T, SPIN> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A guard that keeps its futer alive, so it does not borrow from it.
+pub struct OwnedFuterGuard<T> {
+    // Field order matters: the guard has to release the lock before the Arc
+    // it points into is dropped.
+    guard: FuterGuard<'static, T>,
+    _futer: Arc<Futer<T>>,
+}
 
-impl<'a, T> std::ops::Deref for FuterGuard<'a, T> {
+impl<T> std::ops::Deref for OwnedFuterGuard<T> {
     type Target = T;
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        &*self.0
+        &self.guard
     }
 }
 
-impl<'a, T> std::ops::DerefMut for FuterGuard<'a, T> {
+impl<T> std::ops::DerefMut for OwnedFuterGuard<T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut *self.0
+        &mut self.guard
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for OwnedFuterGuard<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.guard.fmt(f)
     }
 }
 
@@ -187,13 +475,17 @@ mod tests {
     struct MockFutexCalls;
 
     impl Futex for MockFutexCalls {
-        fn futex_wake(lock: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64 {
+        fn futex_wake_one(lock: &AtomicU32) -> io::Result<usize> {
             FUTEX_WAKE_CALL_COUNTER.fetch_add(1, Ordering::SeqCst);
-            futex_wake(lock, val, timeout)
+            futex_wake_one(lock, FutexFlags::PRIVATE)
         }
-        fn futex_wait(lock: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64 {
+        fn futex_wake_all(lock: &AtomicU32) -> io::Result<usize> {
+            FUTEX_WAKE_CALL_COUNTER.fetch_add(1, Ordering::SeqCst);
+            futex_wake_all(lock, FutexFlags::PRIVATE)
+        }
+        fn futex_wait(lock: &AtomicU32, val: u32, timeout: Option<&FutexTimeout>) -> Result<i64, FutexError> {
             FUTEX_WAIT_CALL_COUNTER.fetch_add(1, Ordering::SeqCst);
-            futex_wait(lock, val, timeout)
+            futex_wait(lock, val, timeout, FutexFlags::PRIVATE)
         }
     }
 
@@ -277,7 +569,7 @@ mod tests {
                     barrier_clone.wait();
                     for _ in 0..NUM_ITER {
                         let mut lock = futer_clone.lock().unwrap();
-                        *lock = *lock + 1;
+                        *lock += 1;
                         Futer::unlock(lock);
                     }
                     finished_barrier_clone.wait();
@@ -319,6 +611,282 @@ mod tests {
         }
     }
 
+    #[test]
+    fn with_lock_releases_after_closure() {
+        let futer = Futer::new(32);
+
+        let res = futer.with_lock(|val| {
+            *val = 42;
+            *val + 1
+        });
+
+        assert_eq!(res, 43);
+        assert_eq!(*futer.try_lock().unwrap(), 42);
+    }
+
+    #[test]
+    fn with_lock_releases_on_panic() {
+        let futer = Futer::new(32);
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            futer.with_lock(|_| panic!("panic inside critical section"))
+        }));
+
+        assert!(res.is_err());
+        assert!(futer.try_lock().is_ok());
+    }
+
+    #[test]
+    fn force_unlock_recovers_forgotten_guard() {
+        let futer = Futer::new(32);
+
+        std::mem::forget(futer.lock().unwrap());
+        assert_eq!(futer.try_lock().unwrap_err(), TryLockError::WouldBlock);
+
+        // Safety: the only guard was forgotten, so none is live.
+        unsafe { futer.force_unlock() };
+        assert_eq!(*futer.try_lock().unwrap(), 32);
+    }
+
+    #[test]
+    fn force_unlock_wakes_waiters() {
+        use std::sync::Arc;
+        use std::thread::{sleep, spawn};
+        use std::time::Duration;
+
+        let futer = Arc::new(Futer::new(32));
+        std::mem::forget(futer.lock().unwrap());
+
+        let futer2 = Arc::clone(&futer);
+        let handle = spawn(move || *futer2.lock().unwrap());
+
+        sleep(Duration::from_millis(500));
+        // Safety: the only guard was forgotten, so none is live.
+        unsafe { futer.force_unlock() };
+
+        assert_eq!(handle.join().unwrap(), 32);
+    }
+
+    #[test]
+    fn new_locked_starts_locked() {
+        let (futer, mut guard) = Futer::new_locked(32);
+        assert_eq!(futer.try_lock().unwrap_err(), TryLockError::WouldBlock);
+
+        *guard = 42;
+        drop(guard);
+
+        assert_eq!(*futer.lock().unwrap(), 42);
+    }
+
+    #[test]
+    fn new_locked_blocks_other_threads_until_released() {
+        use std::thread::{sleep, spawn};
+        use std::time::Duration;
+
+        let (futer, mut guard) = Futer::new_locked(0);
+        let futer2 = Arc::clone(&futer);
+        let handle = spawn(move || *futer2.lock().unwrap());
+
+        sleep(Duration::from_millis(500));
+        *guard = 42;
+        drop(guard);
+
+        assert_eq!(handle.join().unwrap(), 42);
+    }
+
+    #[test]
+    fn guard_forwards_display() {
+        let futer = Futer::new(String::from("hello"));
+        let guard = futer.lock().unwrap();
+
+        assert_eq!(format!("{}", guard), "hello");
+        assert_eq!(format!("{:>7}", guard), "  hello");
+    }
+
+    #[test]
+    fn lock_many_returns_guards_in_input_order() {
+        let a = Futer::new(1);
+        let b = Futer::new(2);
+        let c = Futer::new(3);
+
+        let guards = lock_many(&[&c, &a, &b]);
+        let vals: Vec<u32> = guards.iter().map(|g| **g).collect();
+        assert_eq!(vals, vec![3, 1, 2]);
+
+        assert!(a.try_lock().is_err());
+        drop(guards);
+        assert!(a.try_lock().is_ok());
+    }
+
+    #[test]
+    #[should_panic]
+    fn lock_many_rejects_duplicates() {
+        let a = Futer::new(1);
+        let _ = lock_many(&[&a, &a]);
+    }
+
+    #[test]
+    fn lock_many_opposite_order_does_not_deadlock() {
+        use std::thread::spawn;
+
+        const NUM_ITER: usize = 1000;
+
+        let a = Arc::new(Futer::new(0));
+        let b = Arc::new(Futer::new(0));
+
+        let handles: Vec<_> = (0..2)
+            .map(|n| {
+                let a = Arc::clone(&a);
+                let b = Arc::clone(&b);
+                spawn(move || {
+                    for _ in 0..NUM_ITER {
+                        let pair = if n == 0 { [&*a, &*b] } else { [&*b, &*a] };
+                        let mut guards = lock_many(&pair);
+                        *guards[0] += 1;
+                        *guards[1] += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*a.lock().unwrap(), 2 * NUM_ITER);
+        assert_eq!(*b.lock().unwrap(), 2 * NUM_ITER);
+    }
+
+    #[test]
+    fn unlocked_lets_other_threads_lock() {
+        use std::thread::spawn;
+
+        let futer = Arc::new(Futer::new(32u32));
+        let mut guard = futer.lock().unwrap();
+        let ptr = &*guard as *const u32;
+
+        let futer2 = Arc::clone(&futer);
+        FuterGuard::unlocked(&mut guard, move || {
+            spawn(move || *futer2.lock().unwrap() = 42).join().unwrap();
+        });
+
+        assert_eq!(*guard, 42);
+        assert_eq!(&*guard as *const u32, ptr);
+        assert!(futer.try_lock().is_err());
+    }
+
+    #[test]
+    fn unlocked_relocks_on_panic() {
+        let futer = Futer::new(32);
+        let mut guard = futer.lock().unwrap();
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            FuterGuard::unlocked(&mut guard, || panic!("panic while unlocked"))
+        }));
+
+        assert!(res.is_err());
+        assert!(futer.try_lock().is_err());
+        drop(guard);
+        assert!(futer.try_lock().is_ok());
+    }
+
+    #[test]
+    fn try_lock_never_fails_on_free_lock() {
+        use std::sync::Barrier;
+        use std::thread::spawn;
+
+        const NUM_THREADS: usize = 4;
+        const NUM_ROUNDS: usize = 200;
+        const NUM_ITER: usize = 100;
+
+        let futer = Arc::new(Futer::new(0));
+        let barrier = Arc::new(Barrier::new(NUM_THREADS + 1));
+
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let futer = Arc::clone(&futer);
+                let barrier = Arc::clone(&barrier);
+                spawn(move || {
+                    for _ in 0..NUM_ROUNDS {
+                        barrier.wait();
+                        for _ in 0..NUM_ITER {
+                            // Mix blocking and non-blocking acquisitions so the
+                            // word keeps moving through the CONTESTED state.
+                            if let Ok(mut lock) = futer.try_lock() {
+                                *lock += 1;
+                            }
+                            *futer.lock().unwrap() += 1;
+                        }
+                        barrier.wait();
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..NUM_ROUNDS {
+            barrier.wait();
+            barrier.wait();
+            // Every worker has dropped its guards, so the lock is free.
+            for _ in 0..NUM_ITER {
+                assert!(futer.try_lock().is_ok());
+            }
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn spinning_futer_sync_test() {
+        use std::thread::spawn;
+
+        const NUM_THREADS: usize = 4;
+        const NUM_ITER: usize = 1000;
+
+        let futer = Arc::new(Futer::<_, 100>::with_spin(0));
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let futer = Arc::clone(&futer);
+                spawn(move || {
+                    for _ in 0..NUM_ITER {
+                        *futer.lock().unwrap() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*futer.lock().unwrap(), NUM_THREADS * NUM_ITER);
+    }
+
+    #[test]
+    fn guard_can_be_sent_to_threads() {
+        let futer = Futer::new(32);
+        let mut guard = futer.lock().unwrap();
+
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(*guard, 32));
+        });
+        std::thread::scope(|s| {
+            s.spawn(move || *guard = 42);
+        });
+
+        assert_eq!(*futer.try_lock().unwrap(), 42);
+    }
+
+    // Only compiles with may_dangle: s is gone by the time the guard drops.
+    #[cfg(not(feature = "stable"))]
+    #[test]
+    fn guard_may_outlive_borrowed_values() {
+        let futer = Futer::new(Vec::new());
+        let mut guard = futer.lock().unwrap();
+        let s = String::from("borrowed");
+        guard.push(&s);
+        assert_eq!(guard.len(), 1);
+    }
+
     #[test]
     fn only_syscalls_when_contested() {
         let futer_internal = FuterInternal::<u32, MockFutexCalls>::new(0);
@@ -329,4 +897,70 @@ mod tests {
         assert_eq!(FUTEX_WAIT_CALL_COUNTER.load(Ordering::SeqCst), 0);
         assert_eq!(FUTEX_WAKE_CALL_COUNTER.load(Ordering::SeqCst), 0);
     }
+
+    // Behaves as if the owner unlocked right before the wait went to sleep.
+    struct WouldBlockFutexCalls;
+
+    impl Futex for WouldBlockFutexCalls {
+        fn futex_wake_one(_lock: &AtomicU32) -> io::Result<usize> {
+            Ok(0)
+        }
+        fn futex_wake_all(_lock: &AtomicU32) -> io::Result<usize> {
+            Ok(0)
+        }
+        fn futex_wait(lock: &AtomicU32, _val: u32, _timeout: Option<&FutexTimeout>) -> Result<i64, FutexError> {
+            lock.store(UNLOCKED, Ordering::Release);
+            Err(FutexError::WouldBlock)
+        }
+    }
+
+    #[test]
+    fn acquire_rechecks_after_would_block() {
+        let lock = AtomicU32::new(CONTESTED);
+        acquire::<WouldBlockFutexCalls, DEFAULT_SPIN>(&lock);
+        assert_eq!(lock.load(Ordering::SeqCst), CONTESTED);
+    }
+}
+
+#[cfg(all(test, not(feature = "stable")))]
+mod benches {
+    use super::*;
+    use std::sync::Barrier;
+    use std::thread::spawn;
+    use test::Bencher;
+
+    const NUM_THREADS: usize = 4;
+    const NUM_ITER: usize = 1000;
+
+    fn contended<const SPIN: usize>(b: &mut Bencher) {
+        b.iter(|| {
+            let futer = Arc::new(Futer::<_, SPIN>::with_spin(0));
+            let barrier = Arc::new(Barrier::new(NUM_THREADS));
+            let handles: Vec<_> = (0..NUM_THREADS)
+                .map(|_| {
+                    let futer = Arc::clone(&futer);
+                    let barrier = Arc::clone(&barrier);
+                    spawn(move || {
+                        barrier.wait();
+                        for _ in 0..NUM_ITER {
+                            *futer.lock().unwrap() += 1;
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn contended_spin_0(b: &mut Bencher) {
+        contended::<0>(b);
+    }
+
+    #[bench]
+    fn contended_spin_100(b: &mut Bencher) {
+        contended::<100>(b);
+    }
 }
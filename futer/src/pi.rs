@@ -0,0 +1,193 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use futex_ffi::{futex_lock_pi, futex_unlock_pi, gettid, FutexFlags};
+
+const UNLOCKED: u32 = 0;
+
+thread_local! {
+    // Saves a syscall on every lock. The TID of a thread only changes across
+    // fork: the child's thread inherits the cache of the thread that forked,
+    // so its TID here is stale and FUTEX_LOCK_PI would fail or attribute the
+    // lock to the wrong thread. See the fork note on FuterPI.
+    static TID: u32 = gettid();
+}
+
+/// A futer using the kernel's priority-inheritance futex operations.
+///
+/// While a thread is blocked on the lock, the kernel boosts the priority of
+/// the owner to that of the highest priority waiter, which avoids priority
+/// inversion for real-time workloads.
+///
+/// This only works on Linux. Following the PI futex convention, the lock word
+/// holds the TID of the owning thread, and the kernel sets the waiters bit when
+/// a thread has to block, so unlocking must go through `FUTEX_UNLOCK_PI`.
+///
+/// The TID is cached per thread. A child process created by `fork` keeps the
+/// forking thread's TID in that cache, so it must not lock a `FuterPI` from
+/// the thread that forked without exec'ing first, just as it should not touch
+/// most locks after a fork.
+pub struct FuterPI<T> {
+    val: Box<T>,
+    lock: Box<AtomicU32>,
+}
+
+// Safety: see the Send and Sync implementations of Futer.
+unsafe impl<T: Send> Send for FuterPI<T> {}
+unsafe impl<T: Send> Sync for FuterPI<T> {}
+
+impl<T> FuterPI<T> {
+    pub fn new(val: T) -> Self {
+        let val = Box::new(val);
+        let lock = Box::new(AtomicU32::new(UNLOCKED));
+        Self { val, lock }
+    }
+
+    pub fn lock(&self) -> FuterPIGuard<'_, T> {
+        let tid = TID.with(|tid| *tid);
+        if self
+            .lock
+            .compare_exchange(UNLOCKED, tid, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // The kernel only returns success once it has written our TID
+            // into the word. EAGAIN (the owner was exiting) and EINTR just
+            // mean we have to try again, anything else (like EDEADLK for a
+            // lock we already hold) would fail forever.
+            loop {
+                match futex_lock_pi(&self.lock, None, FutexFlags::PRIVATE) {
+                    Ok(_) => break,
+                    Err(err)
+                        if err.kind() == io::ErrorKind::WouldBlock
+                            || err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => panic!("futex_lock_pi failed: {}", err),
+                }
+            }
+        }
+        FuterPIGuard {
+            ptr: self.val.as_ref() as *const T,
+            lock: self.lock.as_ref(),
+            tid,
+        }
+    }
+
+    pub fn unlock(guard: FuterPIGuard<T>) {
+        drop(guard)
+    }
+}
+
+/// The guard for a locked `FuterPI`.
+///
+/// PI futexes are owned by a thread rather than by a value, so the guard is
+/// neither `Send` nor `Sync`: it must be dropped on the thread that locked.
+pub struct FuterPIGuard<'a, T> {
+    ptr: *const T,
+    lock: &'a AtomicU32,
+    tid: u32,
+}
+
+impl<'a, T> std::ops::Deref for FuterPIGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: same as the guard of Futer, the pointer comes from a box
+        // that outlives 'a.
+        unsafe { self.ptr.as_ref().unwrap() }
+    }
+}
+
+impl<'a, T> std::ops::DerefMut for FuterPIGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let ptr_mut = self.ptr as *mut T;
+        // Safety: holding the guard means we own the lock.
+        unsafe { ptr_mut.as_mut().unwrap() }
+    }
+}
+
+impl<'a, T: fmt::Display> fmt::Display for FuterPIGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T> Drop for FuterPIGuard<'a, T> {
+    fn drop(&mut self) {
+        // If the word is no longer just our TID, the kernel has set the
+        // waiters bit and has to hand the lock over to one of them. Failing
+        // here would leave the word owned and the next locker stuck, so it
+        // must not go unnoticed.
+        if self
+            .lock
+            .compare_exchange(self.tid, UNLOCKED, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            futex_unlock_pi(self.lock, FutexFlags::PRIVATE).expect("futex_unlock_pi failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+    use std::thread::spawn;
+
+    #[test]
+    fn pi_lock_api() {
+        let futer = FuterPI::new(32);
+        assert_eq!(*futer.lock(), 32);
+
+        *futer.lock() = 42;
+        assert_eq!(*futer.lock(), 42);
+    }
+
+    #[test]
+    fn pi_word_holds_owner_tid() {
+        let futer = FuterPI::new(32);
+        let guard = futer.lock();
+
+        assert_eq!(futer.lock.load(Ordering::Relaxed), gettid());
+        FuterPI::unlock(guard);
+        assert_eq!(futer.lock.load(Ordering::Relaxed), UNLOCKED);
+    }
+
+    #[test]
+    #[should_panic(expected = "futex_lock_pi failed")]
+    fn pi_recursive_lock_panics() {
+        let futer = FuterPI::new(32);
+        let _guard = futer.lock();
+        // The kernel refuses with EDEADLK, which retrying would never fix
+        let _ = futer.lock();
+    }
+
+    #[test]
+    fn pi_basic_sync_test() {
+        const NUM_THREADS: usize = 5;
+        const NUM_ITER: usize = 1000;
+
+        let barrier = Arc::new(Barrier::new(NUM_THREADS));
+        let futer = Arc::new(FuterPI::new(0));
+
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let futer = Arc::clone(&futer);
+                let barrier = Arc::clone(&barrier);
+                spawn(move || {
+                    barrier.wait();
+                    for _ in 0..NUM_ITER {
+                        let mut lock = futer.lock();
+                        *lock += 1;
+                        FuterPI::unlock(lock);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*futer.lock(), NUM_THREADS * NUM_ITER);
+        assert_eq!(futer.lock.load(Ordering::Relaxed), UNLOCKED);
+    }
+}
@@ -0,0 +1,9 @@
+use std::rc::Rc;
+
+use futer::Futer;
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<Futer<Rc<u32>>>();
+}
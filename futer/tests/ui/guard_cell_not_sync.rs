@@ -0,0 +1,12 @@
+use std::cell::Cell;
+
+use futer::Futer;
+
+fn main() {
+    let futer = Futer::new(Cell::new(32u32));
+    let guard = futer.lock().unwrap();
+
+    std::thread::scope(|s| {
+        s.spawn(|| guard.set(42));
+    });
+}
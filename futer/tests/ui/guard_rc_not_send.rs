@@ -0,0 +1,12 @@
+use std::rc::Rc;
+
+use futer::Futer;
+
+fn main() {
+    let futer = Futer::new(Rc::new(32u32));
+    let guard = futer.lock().unwrap();
+
+    std::thread::scope(|s| {
+        s.spawn(move || drop(guard));
+    });
+}
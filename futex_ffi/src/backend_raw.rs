@@ -0,0 +1,124 @@
+use core::sync::atomic::AtomicU32;
+
+#[cfg(target_pointer_width = "64")]
+#[allow(non_camel_case_types)]
+pub type c_long = i64;
+#[cfg(target_pointer_width = "32")]
+#[allow(non_camel_case_types)]
+pub type c_long = i32;
+// The futex syscall of 32-bit targets takes the time32 timespec, its time64
+// variant is a separate syscall.
+#[allow(non_camel_case_types)]
+pub type c_time_t = c_long;
+#[allow(non_camel_case_types)]
+pub type c_pid_t = i32;
+#[allow(non_camel_case_types)]
+pub type c_clockid_t = i32;
+
+// Laid out like the kernel's struct timespec, with both fields a signed
+// long.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct c_timespec {
+    pub tv_sec: c_time_t,
+    pub tv_nsec: c_long,
+}
+
+extern "C" {
+    #[link_name = "syscall"]
+    pub fn syscall_untyped(syscall: c_long, ...) -> c_long;
+
+    #[link_name = "gettid"]
+    pub fn c_gettid() -> c_pid_t;
+
+    #[link_name = "clock_gettime"]
+    pub fn c_clock_gettime(clock: c_clockid_t, timespec: *mut c_timespec) -> i32;
+
+    // Where the C library keeps the errno of the calling thread, both glibc
+    // and musl export it under this name.
+    #[link_name = "__errno_location"]
+    pub fn c_errno_location() -> *mut i32;
+}
+
+pub unsafe fn syscall(
+    syscall: c_long,
+    futex_addr: *const AtomicU32,
+    op: i32,
+    val: u32,
+    timeout: *const c_timespec,
+    uaddr2: *const u32,
+    val3: u32,
+) -> i64 {
+    syscall_untyped(syscall, futex_addr, op, val, timeout, uaddr2, val3) as i64
+}
+
+// The futex syscall number differs between architectures.
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "riscv64"
+)))]
+compile_error!("feature \"backend-raw\" only supports x86_64, x86, aarch64, arm and riscv64");
+
+#[cfg(target_arch = "x86_64")]
+pub const SYS_FUTEX: c_long = 202;
+#[cfg(any(target_arch = "x86", target_arch = "arm"))]
+pub const SYS_FUTEX: c_long = 240;
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+pub const SYS_FUTEX: c_long = 98;
+pub const FUTEX_WAIT: i32 = 0;
+pub const FUTEX_WAKE: i32 = 1;
+pub const FUTEX_REQUEUE: i32 = 3;
+pub const FUTEX_CMP_REQUEUE: i32 = 4;
+pub const FUTEX_WAKE_OP: i32 = 5;
+pub const FUTEX_LOCK_PI: i32 = 6;
+pub const FUTEX_UNLOCK_PI: i32 = 7;
+pub const FUTEX_WAIT_BITSET: i32 = 9;
+pub const FUTEX_WAKE_BITSET: i32 = 10;
+pub const FUTEX_PRIVATE_FLAG: i32 = 128;
+pub const FUTEX_CLOCK_REALTIME: i32 = 256;
+pub const FUTEX_BITSET_MATCH_ANY: i32 = -1;
+
+pub const CLOCK_REALTIME: c_clockid_t = 0;
+pub const CLOCK_MONOTONIC: c_clockid_t = 1;
+
+// The errno values the waits tell apart, the same on every supported
+// architecture. With std, io::Error does this for all but EINTR.
+pub const EINTR: i32 = 4;
+#[cfg(not(feature = "std"))]
+pub const EAGAIN: i32 = 11;
+#[cfg(not(feature = "std"))]
+pub const ETIMEDOUT: i32 = 110;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_constants_match_libc() {
+        assert_eq!(SYS_FUTEX, libc::SYS_futex);
+        assert_eq!(FUTEX_WAIT, libc::FUTEX_WAIT);
+        assert_eq!(FUTEX_WAKE, libc::FUTEX_WAKE);
+        assert_eq!(FUTEX_REQUEUE, libc::FUTEX_REQUEUE);
+        assert_eq!(FUTEX_CMP_REQUEUE, libc::FUTEX_CMP_REQUEUE);
+        assert_eq!(FUTEX_WAKE_OP, libc::FUTEX_WAKE_OP);
+        assert_eq!(FUTEX_LOCK_PI, libc::FUTEX_LOCK_PI);
+        assert_eq!(FUTEX_UNLOCK_PI, libc::FUTEX_UNLOCK_PI);
+        assert_eq!(FUTEX_WAIT_BITSET, libc::FUTEX_WAIT_BITSET);
+        assert_eq!(FUTEX_WAKE_BITSET, libc::FUTEX_WAKE_BITSET);
+        assert_eq!(FUTEX_PRIVATE_FLAG, libc::FUTEX_PRIVATE_FLAG);
+        assert_eq!(FUTEX_CLOCK_REALTIME, libc::FUTEX_CLOCK_REALTIME);
+        assert_eq!(FUTEX_BITSET_MATCH_ANY, libc::FUTEX_BITSET_MATCH_ANY);
+        assert_eq!(CLOCK_REALTIME, libc::CLOCK_REALTIME);
+        assert_eq!(CLOCK_MONOTONIC, libc::CLOCK_MONOTONIC);
+        assert_eq!(EINTR, libc::EINTR);
+        #[cfg(not(feature = "std"))]
+        assert_eq!((EAGAIN, ETIMEDOUT), (libc::EAGAIN, libc::ETIMEDOUT));
+        assert_eq!(
+            std::mem::size_of::<c_timespec>(),
+            std::mem::size_of::<libc::timespec>()
+        );
+    }
+}
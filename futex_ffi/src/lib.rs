@@ -1,48 +1,302 @@
-#[cfg(all(feature = "libc", feature = "libcosti"))]
-compile_error!("feature \"libc\" and feature \"libcosti\" cannot be enabled at the same time");
+// Only the Linux backends get by without std, see the std feature.
+#![cfg_attr(not(feature = "std"), no_std)]
 
-#[cfg(feature = "libc")]
+#[cfg(all(
+    target_os = "linux",
+    not(any(feature = "backend-libc", feature = "backend-raw"))
+))]
+compile_error!("one of feature \"backend-libc\" or feature \"backend-raw\" has to be enabled");
+#[cfg(all(not(target_os = "linux"), not(feature = "std")))]
+compile_error!("feature \"std\" is required outside of Linux");
+
+// The tests spawn threads and measure time either way.
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+// The backend-libc and backend-raw features pick how the Linux futex syscall
+// is bound, see Cargo.toml. Other platforms have their own module providing
+// futex_wait and futex_wake with the same signatures, while the rest of the
+// operations are Linux only.
+#[cfg(all(target_os = "linux", feature = "backend-raw"))]
+mod backend_raw;
+#[cfg(all(
+    target_os = "linux",
+    feature = "backend-libc",
+    not(feature = "backend-raw")
+))]
 mod lib_c;
-#[cfg(feature = "libcosti")]
-mod lib_costi;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(windows)]
+mod windows;
 
-#[cfg(feature = "libc")]
-use crate::lib_c::*;
-#[cfg(feature = "libcosti")]
-use crate::lib_costi::*;
+#[cfg(target_os = "linux")]
+pub use crate::linux::*;
+#[cfg(target_os = "macos")]
+pub use crate::macos::*;
+#[cfg(windows)]
+pub use crate::windows::*;
 
-use std::ptr::null;
-use std::sync::atomic::AtomicU32;
+use core::convert::TryFrom;
+use core::error::Error;
+use core::fmt;
+use core::sync::atomic::AtomicU32;
+#[cfg(feature = "std")]
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::time::Instant;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct FutexTimeout(i64, i64);
 
-impl From<FutexTimeout> for c_timespec {
-    fn from(timeout: FutexTimeout) -> Self {
-        println!("here");
-        let tv_sec = timeout.0;
-        let tv_nsec = timeout.1;
-        Self { tv_sec, tv_nsec }
+impl FutexTimeout {
+    // A timeout of secs seconds plus nanos nanoseconds. The kernel refuses
+    // timespecs with nanos outside of a second, so these are rejected here
+    // rather than failing with EINVAL once the timeout is used.
+    pub fn new(secs: i64, nanos: i64) -> Result<Self, FutexTimeoutError> {
+        let timeout = FutexTimeout(secs, nanos);
+        if timeout.has_valid_nanos() {
+            Ok(timeout)
+        } else {
+            Err(FutexTimeoutError)
+        }
+    }
+
+    // Like new, but hands nanos on as they are. A wait with nanos outside of
+    // a second fails with an Other error, EINVAL from the kernel on Linux
+    // and InvalidInput elsewhere, instead of being rejected up front.
+    pub fn new_raw(secs: i64, nanos: i64) -> Self {
+        FutexTimeout(secs, nanos)
+    }
+
+    pub(crate) fn has_valid_nanos(&self) -> bool {
+        (0..1_000_000_000).contains(&self.1)
+    }
+
+    pub fn from_secs(secs: u64) -> Self {
+        Duration::from_secs(secs).into()
+    }
+
+    pub fn from_millis(millis: u64) -> Self {
+        Duration::from_millis(millis).into()
+    }
+
+    // Carries whole seconds over, so nanos stays below a second.
+    pub fn from_nanos(nanos: u64) -> Self {
+        Duration::from_nanos(nanos).into()
+    }
+
+    pub fn secs(&self) -> i64 {
+        self.0
+    }
+
+    pub fn nanos(&self) -> i64 {
+        self.1
+    }
+
+    // Negative parts count as zero. Waits check has_valid_nanos first.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn as_duration(&self) -> Duration {
+        let nanos = self.1.clamp(0, 999_999_999) as u32;
+        Duration::new(self.0.max(0) as u64, nanos)
+    }
+}
+
+impl From<Duration> for FutexTimeout {
+    fn from(duration: Duration) -> Self {
+        // Durations longer than i64::MAX seconds are clamped, they would not
+        // expire in any case.
+        let secs = i64::try_from(duration.as_secs()).unwrap_or(i64::MAX);
+        FutexTimeout(secs, duration.subsec_nanos() as i64)
+    }
+}
+
+// Returned by FutexTimeout::new for nanoseconds outside of 0..1_000_000_000.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FutexTimeoutError;
+
+impl fmt::Display for FutexTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "timeout nanoseconds out of range".fmt(f)
+    }
+}
+
+impl Error for FutexTimeoutError {}
+
+// The error the futex operations fail with.
+#[cfg(feature = "std")]
+pub type OsError = io::Error;
+#[cfg(not(feature = "std"))]
+pub type OsError = Errno;
+
+// Stands in for io::Error without std. Mirrors the part of its API that
+// deals with raw OS errors, so callers can look at either the same way.
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Errno(i32);
+
+#[cfg(not(feature = "std"))]
+impl Errno {
+    pub fn from_raw_os_error(code: i32) -> Self {
+        Errno(code)
+    }
+
+    pub fn raw_os_error(&self) -> Option<i32> {
+        Some(self.0)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "os error {}", self.0)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Error for Errno {}
+
+// Why a wait returned without being woken. Only Other is a real error, the
+// rest are expected outcomes callers usually handle by looking at the word
+// again.
+#[derive(Debug)]
+pub enum FutexError {
+    // EAGAIN: the word did not hold the expected value, so the wait did not
+    // sleep at all.
+    WouldBlock,
+    // ETIMEDOUT: the timeout elapsed before a wakeup.
+    TimedOut,
+    // EINTR: a signal handler ran. The Linux waits retry on their own, so
+    // only other platforms report it.
+    Interrupted,
+    // Anything else, like EFAULT for a bad address or ENOSYS.
+    Other(OsError),
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for FutexError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::WouldBlock => FutexError::WouldBlock,
+            io::ErrorKind::TimedOut => FutexError::TimedOut,
+            io::ErrorKind::Interrupted => FutexError::Interrupted,
+            _ => FutexError::Other(err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<FutexError> for io::Error {
+    fn from(err: FutexError) -> Self {
+        match err {
+            FutexError::WouldBlock => io::ErrorKind::WouldBlock.into(),
+            FutexError::TimedOut => io::ErrorKind::TimedOut.into(),
+            FutexError::Interrupted => io::ErrorKind::Interrupted.into(),
+            FutexError::Other(err) => err,
+        }
     }
 }
 
-unsafe fn futex(futex_ref: &AtomicU32, op: i32, val: u32, timeout: Option<FutexTimeout>) -> i64 {
-    let futex_addr = futex_ref as *const AtomicU32;
-    let timeout_ptr = match timeout {
-        None => null(),
-        Some(duration) => {
-            let timespec = c_timespec::from(duration);
-            (&timespec) as *const c_timespec
+impl fmt::Display for FutexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FutexError::WouldBlock => "futex word did not hold the expected value".fmt(f),
+            FutexError::TimedOut => "futex wait timed out".fmt(f),
+            FutexError::Interrupted => "futex wait interrupted by a signal".fmt(f),
+            FutexError::Other(err) => err.fmt(f),
         }
-    };
-    syscall(SYS_FUTEX, futex_addr, op, val, timeout_ptr, null(), 0)
+    }
+}
+
+impl Error for FutexError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FutexError::Other(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// Turns the -1 returned on failure into the error errno says. The waits turn
+// it into a FutexError, which tells EAGAIN, ETIMEDOUT and EINTR apart from
+// real errors.
+#[cfg(unix)]
+fn check(ret: i64) -> Result<i64, OsError> {
+    if ret == -1 {
+        Err(last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+// Flags modifying a futex operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FutexFlags(i32);
+
+impl FutexFlags {
+    // The futex word may live in memory shared with other processes.
+    pub const SHARED: FutexFlags = FutexFlags(SHARED_FLAG);
+    // The futex word is only used within this process, which lets the kernel
+    // skip looking up the backing mapping. Waiters and wakers of a word have
+    // to agree on the flag, or they will not find each other.
+    pub const PRIVATE: FutexFlags = FutexFlags(PRIVATE_FLAG);
 }
 
-pub fn futex_wait(futex_addr: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64 {
-    unsafe { futex(futex_addr, FUTEX_WAIT, val, timeout) }
+// Wakes a single waiter, to hand something over to just one thread.
+pub fn futex_wake_one(futex_addr: &AtomicU32, flags: FutexFlags) -> Result<usize, OsError> {
+    futex_wake(futex_addr, 1, None, flags)
 }
 
-pub fn futex_wake(futex_addr: &AtomicU32, val: u32, timeout: Option<FutexTimeout>) -> i64 {
-    unsafe { futex(futex_addr, FUTEX_WAKE, val, timeout) }
+// Wakes every waiter. The kernel reads the count as a signed int, so this
+// passes i32::MAX: u32::MAX would read as -1 and wake a single thread.
+pub fn futex_wake_all(futex_addr: &AtomicU32, flags: FutexFlags) -> Result<usize, OsError> {
+    futex_wake(futex_addr, i32::MAX as u32, None, flags)
+}
+
+// Blocks for as long as predicate holds for the value of the futex word.
+// The word is loaded again after every wakeup, spurious or not, and the wait
+// only sleeps if the word still holds the value the predicate was checked
+// against, so a change between the check and the wait is never missed.
+//
+// Fails with TimedOut if the predicate still holds once timeout elapsed. The
+// timeout covers the whole call, not every single wait. Needs std for its
+// clock.
+#[cfg(feature = "std")]
+pub fn futex_wait_while<F: Fn(u32) -> bool>(
+    futex_addr: &AtomicU32,
+    predicate: F,
+    timeout: Option<Duration>,
+    flags: FutexFlags,
+) -> Result<(), FutexError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let current = futex_addr.load(Ordering::Acquire);
+        if !predicate(current) {
+            return Ok(());
+        }
+        let remaining = match deadline {
+            None => None,
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(FutexError::TimedOut);
+                }
+                Some(FutexTimeout::from(deadline - now))
+            }
+        };
+        match futex_wait(futex_addr, current, remaining.as_ref(), flags) {
+            Ok(_)
+            | Err(FutexError::WouldBlock)
+            | Err(FutexError::Interrupted)
+            | Err(FutexError::TimedOut) => {}
+            Err(err) => return Err(err),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -52,39 +306,119 @@ mod tests {
     use std::sync::Arc;
     use std::thread::{sleep, spawn};
     use std::time::Duration;
+    #[cfg(not(feature = "std"))]
+    use std::vec::Vec;
 
     #[test]
-    fn syscall_basic_functionality() {
-        let shared_int = AtomicU32::new(0);
-        let shared_int_addr = &shared_int as *const AtomicU32;
-        let res = unsafe { syscall(SYS_FUTEX, shared_int_addr, FUTEX_WAIT, 1, null(), null(), 0) };
-        assert_eq!(res, -1);
+    fn futex_timeout_from_duration() {
+        let timeout = FutexTimeout::from(Duration::from_millis(1500));
+        assert_eq!((timeout.0, timeout.1), (1, 500_000_000));
+
+        let timeout = FutexTimeout::from(Duration::MAX);
+        assert_eq!((timeout.0, timeout.1), (i64::MAX, 999_999_999));
     }
 
     #[test]
-    fn futext_basic_functionality() {
-        let shared_int = AtomicU32::new(0);
-        let res = unsafe { futex(&shared_int, FUTEX_WAIT, 1, None) };
-        assert_eq!(res, -1);
+    fn futex_timeout_rejects_nanos_out_of_range() {
+        let timeout = FutexTimeout::new(1, 999_999_999).unwrap();
+        assert_eq!((timeout.secs(), timeout.nanos()), (1, 999_999_999));
+        assert!(FutexTimeout::new(1, 0).is_ok());
 
-        let res = unsafe { futex(&shared_int, FUTEX_WAKE, 1, None) };
-        assert_eq!(res, 0);
+        assert_eq!(FutexTimeout::new(1, 1_000_000_000), Err(FutexTimeoutError));
+        assert_eq!(FutexTimeout::new(0, i64::MAX), Err(FutexTimeoutError));
+        assert_eq!(FutexTimeout::new(0, -1), Err(FutexTimeoutError));
     }
 
     #[test]
-    fn futex_wake_after_wait() {
+    fn futex_timeout_from_units() {
+        let timeout = FutexTimeout::from_nanos(1_500_000_000);
+        assert_eq!((timeout.secs(), timeout.nanos()), (1, 500_000_000));
+
+        let timeout = FutexTimeout::from_nanos(999_999_999);
+        assert_eq!((timeout.secs(), timeout.nanos()), (0, 999_999_999));
+
+        let timeout = FutexTimeout::from_millis(1000);
+        assert_eq!((timeout.secs(), timeout.nanos()), (1, 0));
+
+        let timeout = FutexTimeout::from_millis(2001);
+        assert_eq!((timeout.secs(), timeout.nanos()), (2, 1_000_000));
+
+        let timeout = FutexTimeout::from_secs(u64::MAX);
+        assert_eq!((timeout.secs(), timeout.nanos()), (i64::MAX, 0));
+    }
+
+    #[test]
+    fn futex_wake_one_and_all() {
+        const NUM_THREADS: u32 = 3;
+
+        let shared_int = Arc::new(AtomicU32::new(0));
+        let returned = Arc::new(AtomicU32::new(0));
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let shared_int = Arc::clone(&shared_int);
+                let returned = Arc::clone(&returned);
+                spawn(move || {
+                    let _ = futex_wait(&shared_int, 0, None, FutexFlags::PRIVATE);
+                    returned.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        sleep(Duration::from_millis(500));
+
+        futex_wake_one(&shared_int, FutexFlags::PRIVATE).unwrap();
+        sleep(Duration::from_millis(200));
+        assert_eq!(returned.load(Ordering::SeqCst), 1);
+
+        futex_wake_all(&shared_int, FutexFlags::PRIVATE).unwrap();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(returned.load(Ordering::SeqCst), NUM_THREADS);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn futex_wait_while_predicate_breaks() {
         let shared_int = Arc::new(AtomicU32::new(0));
         let shared_int2 = Arc::clone(&shared_int);
 
-        let handle = spawn(move || futex_wait(shared_int2.as_ref(), 0, None));
+        spawn(move || {
+            // Changes that keep the predicate true do not end the wait
+            for val in 1..5 {
+                sleep(Duration::from_millis(100));
+                shared_int2.store(val, Ordering::Release);
+                futex_wake(&shared_int2, 1, None, FutexFlags::PRIVATE).unwrap();
+            }
+        });
+
+        futex_wait_while(&shared_int, |val| val < 4, None, FutexFlags::PRIVATE).unwrap();
+        assert_eq!(shared_int.load(Ordering::Acquire), 4);
 
-        sleep(Duration::from_millis(2000));
-        let res = futex_wake(&shared_int, 1, None);
-        assert_eq!(res, 1);
+        let start = Instant::now();
+        let err = futex_wait_while(
+            &shared_int,
+            |val| val == 4,
+            Some(Duration::from_millis(200)),
+            FutexFlags::PRIVATE,
+        )
+        .unwrap_err();
+        assert!(matches!(err, FutexError::TimedOut));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn futex_error_from_io_error() {
+        let err = io::Error::from(io::ErrorKind::WouldBlock);
+        assert!(matches!(FutexError::from(err), FutexError::WouldBlock));
+        let err = io::Error::from(io::ErrorKind::TimedOut);
+        assert!(matches!(FutexError::from(err), FutexError::TimedOut));
+        let err = io::Error::from(io::ErrorKind::Interrupted);
+        assert!(matches!(FutexError::from(err), FutexError::Interrupted));
 
-        // Checking that the return value is zero checks both that
-        // the thread was woken up, and with no errors
-        assert_eq!(handle.join().unwrap(), 0);
+        let err = FutexError::from(io::Error::from(io::ErrorKind::InvalidInput));
+        assert!(matches!(&err, FutexError::Other(_)));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidInput);
     }
 
     #[test]
@@ -94,7 +428,12 @@ mod tests {
         let finished2 = Arc::clone(&finished);
 
         spawn(move || {
-            futex_wait(&shared_int, 1, Some(FutexTimeout(0, 500000000)));
+            let _ = futex_wait(
+                &shared_int,
+                1,
+                Some(&FutexTimeout(0, 500000000)),
+                FutexFlags::SHARED,
+            );
             finished2.store(true, Ordering::Relaxed);
         });
 
@@ -1,17 +1,25 @@
-use std::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU32;
 
-use libc::{c_long, syscall as syscall_untyped};
-
-pub use libc::{timespec as c_timespec, SYS_futex as SYS_FUTEX, FUTEX_WAIT, FUTEX_WAKE};
+pub use libc::{
+    __errno_location as c_errno_location, c_long, clock_gettime as c_clock_gettime,
+    clockid_t as c_clockid_t, gettid as c_gettid, syscall as syscall_untyped, time_t as c_time_t,
+    timespec as c_timespec, SYS_futex as SYS_FUTEX, CLOCK_MONOTONIC, CLOCK_REALTIME, EINTR,
+    FUTEX_BITSET_MATCH_ANY, FUTEX_CLOCK_REALTIME, FUTEX_CMP_REQUEUE, FUTEX_LOCK_PI,
+    FUTEX_PRIVATE_FLAG, FUTEX_REQUEUE, FUTEX_UNLOCK_PI, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAKE,
+    FUTEX_WAKE_BITSET, FUTEX_WAKE_OP,
+};
+// Only told apart by hand without std, see Errno.
+#[cfg(not(feature = "std"))]
+pub use libc::{EAGAIN, ETIMEDOUT};
 
 pub unsafe fn syscall(
-    syscall: i64,
+    syscall: c_long,
     futex_addr: *const AtomicU32,
     op: i32,
     val: u32,
     timeout: *const c_timespec,
     uaddr2: *const u32,
     val3: u32,
-) -> c_long {
-    syscall_untyped(syscall, futex_addr, op, val, timeout, uaddr2, val3)
+) -> i64 {
+    syscall_untyped(syscall, futex_addr, op, val, timeout, uaddr2, val3) as i64
 }
@@ -0,0 +1,914 @@
+use core::convert::TryFrom;
+#[cfg(feature = "waitv")]
+use core::marker::PhantomData;
+use core::ptr::null;
+use core::sync::atomic::AtomicU32;
+
+#[cfg(feature = "backend-raw")]
+use crate::backend_raw::*;
+#[cfg(all(feature = "backend-libc", not(feature = "backend-raw")))]
+use crate::lib_c::*;
+
+#[cfg(not(feature = "std"))]
+use crate::Errno;
+use crate::{check, FutexError, FutexFlags, FutexTimeout, OsError};
+
+pub(crate) const SHARED_FLAG: i32 = 0;
+pub(crate) const PRIVATE_FLAG: i32 = FUTEX_PRIVATE_FLAG;
+
+// The errno of the calling thread, so it has to be read right after the call
+// that failed.
+pub(crate) fn last_os_error() -> OsError {
+    OsError::from_raw_os_error(unsafe { *c_errno_location() })
+}
+
+// Without std there are no error kinds to go by, only the errno itself.
+#[cfg(not(feature = "std"))]
+impl From<Errno> for FutexError {
+    fn from(err: Errno) -> Self {
+        match err.0 {
+            EAGAIN => FutexError::WouldBlock,
+            ETIMEDOUT => FutexError::TimedOut,
+            EINTR => FutexError::Interrupted,
+            _ => FutexError::Other(err),
+        }
+    }
+}
+
+// The futex operations wrapped by this crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FutexOp {
+    Wait,
+    Wake,
+    Requeue,
+    CmpRequeue,
+    WakeOp,
+    LockPi,
+    UnlockPi,
+    WaitBitset,
+    WakeBitset,
+}
+
+impl FutexOp {
+    // The op argument of the syscall, with flags applied.
+    pub fn raw(self, flags: FutexFlags) -> i32 {
+        let op = match self {
+            FutexOp::Wait => FUTEX_WAIT,
+            FutexOp::Wake => FUTEX_WAKE,
+            FutexOp::Requeue => FUTEX_REQUEUE,
+            FutexOp::CmpRequeue => FUTEX_CMP_REQUEUE,
+            FutexOp::WakeOp => FUTEX_WAKE_OP,
+            FutexOp::LockPi => FUTEX_LOCK_PI,
+            FutexOp::UnlockPi => FUTEX_UNLOCK_PI,
+            FutexOp::WaitBitset => FUTEX_WAIT_BITSET,
+            FutexOp::WakeBitset => FUTEX_WAKE_BITSET,
+        };
+        op | flags.0
+    }
+}
+
+impl From<&FutexTimeout> for c_timespec {
+    fn from(timeout: &FutexTimeout) -> Self {
+        // On 32-bit targets the seconds have to fit the time32 timespec, and
+        // timeouts past 2038 are clamped to its end.
+        let tv_sec = c_time_t::try_from(timeout.0).unwrap_or(if timeout.0 < 0 {
+            c_time_t::MIN
+        } else {
+            c_time_t::MAX
+        });
+        let tv_nsec = timeout.1 as c_long;
+        Self { tv_sec, tv_nsec }
+    }
+}
+
+unsafe fn futex(
+    futex_ref: &AtomicU32,
+    op: i32,
+    val: u32,
+    timeout: Option<&FutexTimeout>,
+    val3: u32,
+) -> i64 {
+    let futex_addr = futex_ref as *const AtomicU32;
+    // The timespec has to outlive the syscall, so it is kept here rather
+    // than inside the match that takes its address.
+    let timespec = timeout.map(c_timespec::from);
+    let timeout_ptr = match &timespec {
+        None => null(),
+        Some(timespec) => timespec as *const c_timespec,
+    };
+    syscall(SYS_FUTEX, futex_addr, op, val, timeout_ptr, null(), val3)
+}
+
+// A signal handler running while a thread waits makes the wait fail with
+// EINTR. Callers cannot do anything about it, so the wait is issued again,
+// and the kernel checks the word anew. This is only meant for waits without
+// a timeout or with an absolute one, which stays valid across retries.
+fn retry_interrupted(mut wait: impl FnMut() -> Result<i64, OsError>) -> Result<i64, OsError> {
+    loop {
+        match wait() {
+            Err(err) if err.raw_os_error() == Some(EINTR) => {}
+            res => return res,
+        }
+    }
+}
+
+// Interrupted waits are issued again without extending the timeout. To that
+// end a timeout is turned into a CLOCK_MONOTONIC deadline once, and waited
+// for through FUTEX_WAIT_BITSET, whose timeout is absolute, so retries need
+// no further clock reads. Matching any bitset keeps this the same as
+// FUTEX_WAIT towards wakers.
+pub fn futex_wait(
+    futex_addr: &AtomicU32,
+    val: u32,
+    timeout: Option<&FutexTimeout>,
+    flags: FutexFlags,
+) -> Result<i64, FutexError> {
+    let woken = match timeout {
+        None => {
+            let op = FutexOp::Wait.raw(flags);
+            retry_interrupted(|| check(unsafe { futex(futex_addr, op, val, None, 0) }))?
+        }
+        Some(timeout) => {
+            let deadline = deadline_after(timeout);
+            let op = FutexOp::WaitBitset.raw(flags);
+            let bitset = FUTEX_BITSET_MATCH_ANY as u32;
+            retry_interrupted(|| {
+                check(unsafe { futex(futex_addr, op, val, Some(&deadline), bitset) })
+            })?
+        }
+    };
+    Ok(woken)
+}
+
+// Wakes up to val waiters, returning how many were actually woken.
+pub fn futex_wake(
+    futex_addr: &AtomicU32,
+    val: u32,
+    timeout: Option<&FutexTimeout>,
+    flags: FutexFlags,
+) -> Result<usize, OsError> {
+    check(unsafe { futex(futex_addr, FutexOp::Wake.raw(flags), val, timeout, 0) })
+        .map(|woken| woken as usize)
+}
+
+// Like futex_wait, but a waker only wakes this waiter if its bitset shares
+// a bit with the one given here, which must not be zero. Unlike FUTEX_WAIT,
+// the timeout of FUTEX_WAIT_BITSET is an absolute CLOCK_MONOTONIC time.
+pub fn futex_wait_bitset(
+    futex_addr: &AtomicU32,
+    val: u32,
+    bitset: u32,
+    timeout: Option<&FutexTimeout>,
+    flags: FutexFlags,
+) -> Result<i64, FutexError> {
+    let woken = retry_interrupted(|| {
+        check(unsafe {
+            futex(
+                futex_addr,
+                FutexOp::WaitBitset.raw(flags),
+                val,
+                timeout,
+                bitset,
+            )
+        })
+    })?;
+    Ok(woken)
+}
+
+// Wakes up to count waiters whose bitset intersects bitset, returning how
+// many were woken. Waiters that used plain futex_wait match every bitset.
+pub fn futex_wake_bitset(
+    futex_addr: &AtomicU32,
+    count: u32,
+    bitset: u32,
+    flags: FutexFlags,
+) -> Result<usize, OsError> {
+    check(unsafe {
+        futex(
+            futex_addr,
+            FutexOp::WakeBitset.raw(flags),
+            count,
+            None,
+            bitset,
+        )
+    })
+    .map(|woken| woken as usize)
+}
+
+// The clock an absolute timeout is measured against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    // Never jumps, the right clock for deadlines within the process.
+    Monotonic,
+    // Wall-clock time, which follows changes to the system time.
+    Realtime,
+}
+
+impl ClockId {
+    fn raw(self) -> c_clockid_t {
+        match self {
+            ClockId::Monotonic => CLOCK_MONOTONIC,
+            ClockId::Realtime => CLOCK_REALTIME,
+        }
+    }
+}
+
+// The current time of clock, to build deadlines for futex_wait_deadline.
+pub fn clock_now(clock: ClockId) -> FutexTimeout {
+    let mut timespec = c_timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Both clocks always exist, so this cannot fail.
+    unsafe { c_clock_gettime(clock.raw(), &mut timespec) };
+    // The fields are only i64 on 64-bit targets
+    #[allow(clippy::unnecessary_cast)]
+    FutexTimeout(timespec.tv_sec as i64, timespec.tv_nsec as i64)
+}
+
+// The CLOCK_MONOTONIC time once timeout elapsed from now. Out of range
+// nanos are handed on unchanged, for the kernel to refuse with EINVAL
+// rather than to turn into some other deadline.
+fn deadline_after(timeout: &FutexTimeout) -> FutexTimeout {
+    if !timeout.has_valid_nanos() {
+        return *timeout;
+    }
+    let now = clock_now(ClockId::Monotonic);
+    let nanos = now.1 + timeout.1;
+    let secs = now
+        .0
+        .saturating_add(timeout.0)
+        .saturating_add(nanos / 1_000_000_000);
+    FutexTimeout(secs, nanos % 1_000_000_000)
+}
+
+// Like futex_wait, but the deadline is an absolute time of clock rather than
+// a duration, so waiting again after a spurious wakeup does not push it back.
+//
+// The kernel only treats the timeout of FUTEX_WAIT as relative, even with
+// FUTEX_CLOCK_REALTIME set, so this goes through FUTEX_WAIT_BITSET, whose
+// timeout is absolute and measured against CLOCK_MONOTONIC unless
+// FUTEX_CLOCK_REALTIME says otherwise. Matching any bitset makes it behave
+// like FUTEX_WAIT towards wakers.
+pub fn futex_wait_deadline(
+    futex_addr: &AtomicU32,
+    val: u32,
+    deadline: &FutexTimeout,
+    clock: ClockId,
+    flags: FutexFlags,
+) -> Result<i64, FutexError> {
+    let clock_flag = match clock {
+        ClockId::Monotonic => 0,
+        ClockId::Realtime => FUTEX_CLOCK_REALTIME,
+    };
+    let op = FutexOp::WaitBitset.raw(flags) | clock_flag;
+    let bitset = FUTEX_BITSET_MATCH_ANY as u32;
+    let woken =
+        retry_interrupted(|| check(unsafe { futex(futex_addr, op, val, Some(deadline), bitset) }))?;
+    Ok(woken)
+}
+
+// The futex_waitv syscall came after the syscall tables of all
+// architectures were unified, so it has the same number everywhere.
+#[cfg(feature = "waitv")]
+const SYS_FUTEX_WAITV: c_long = 449;
+#[cfg(feature = "waitv")]
+const FUTEX2_SIZE_U32: u32 = 0x02;
+
+// One of the words futex_waitv waits on, and the value it has to hold.
+#[cfg(feature = "waitv")]
+#[repr(C)]
+pub struct FutexWaitEntry<'a> {
+    val: u64,
+    uaddr: u64,
+    flags: u32,
+    __reserved: u32,
+    _futex: PhantomData<&'a AtomicU32>,
+}
+
+#[cfg(feature = "waitv")]
+impl<'a> FutexWaitEntry<'a> {
+    // The FUTEX2 flag for private futexes has the same value as
+    // FUTEX_PRIVATE_FLAG, so flags carries over as is.
+    pub fn new(futex_addr: &'a AtomicU32, val: u32, flags: FutexFlags) -> Self {
+        Self {
+            val: val as u64,
+            uaddr: futex_addr as *const AtomicU32 as usize as u64,
+            flags: FUTEX2_SIZE_U32 | flags.0 as u32,
+            __reserved: 0,
+            _futex: PhantomData,
+        }
+    }
+}
+
+// Unlike the futex syscall, futex_waitv takes a 64-bit timespec on every
+// target.
+#[cfg(feature = "waitv")]
+#[repr(C)]
+struct KernelTimespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+// Blocks until one of the words is woken, returning its index in entries.
+// Like futex_wait it fails with WouldBlock right away if a word does not
+// hold the value of its entry, and with TimedOut once timeout elapses. On
+// kernels older than 5.16 it fails with Other, holding ENOSYS.
+#[cfg(feature = "waitv")]
+pub fn futex_waitv(
+    entries: &[FutexWaitEntry<'_>],
+    timeout: Option<&FutexTimeout>,
+) -> Result<usize, FutexError> {
+    // The kernel only takes an absolute timeout here, so it is turned into a
+    // deadline, and that also keeps retries after EINTR from extending it.
+    let deadline = timeout.map(|timeout| {
+        let deadline = deadline_after(timeout);
+        KernelTimespec {
+            tv_sec: deadline.0,
+            tv_nsec: deadline.1,
+        }
+    });
+    let deadline_ptr = match &deadline {
+        None => null(),
+        Some(deadline) => deadline as *const KernelTimespec,
+    };
+    let index = retry_interrupted(|| {
+        check(unsafe {
+            syscall_untyped(
+                SYS_FUTEX_WAITV,
+                entries.as_ptr(),
+                entries.len() as u32,
+                0u32,
+                deadline_ptr,
+                CLOCK_MONOTONIC,
+            ) as i64
+        })
+    })?;
+    Ok(index as usize)
+}
+
+// The operation FUTEX_WAKE_OP applies to the second futex word.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FutexWakeOp {
+    Set = 0,
+    Add = 1,
+    Or = 2,
+    AndNot = 3,
+    Xor = 4,
+}
+
+// The comparison FUTEX_WAKE_OP makes between the old value of the second
+// futex word and cmparg, deciding whether its waiters are woken too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FutexWakeCmp {
+    Eq = 0,
+    Ne = 1,
+    Lt = 2,
+    Le = 3,
+    Gt = 4,
+    Ge = 5,
+}
+
+// Packs the arguments of futex_wake_op into the single word the kernel
+// expects:
+//
+//     bits 28..32  op
+//     bits 24..28  cmp
+//     bits 12..24  oparg
+//     bits  0..12  cmparg
+//
+// The kernel sign-extends both 12-bit arguments, so they have to be below
+// 2048 to be read back as the value given here.
+pub fn futex_wake_op_encode(op: FutexWakeOp, oparg: u32, cmp: FutexWakeCmp, cmparg: u32) -> u32 {
+    assert!(oparg < 1 << 11, "oparg does not fit in 11 bits");
+    assert!(cmparg < 1 << 11, "cmparg does not fit in 11 bits");
+    ((op as u32) << 28) | ((cmp as u32) << 24) | (oparg << 12) | cmparg
+}
+
+// Atomically does the following, returning how many waiters were woken on
+// both words:
+//
+//     old = *addr2;
+//     *addr2 = old op oparg;
+//     wake count1 waiters of addr1;
+//     if old cmp cmparg { wake count2 waiters of addr2 }
+//
+// with op, oparg, cmp and cmparg packed into encoded_op by
+// futex_wake_op_encode.
+pub fn futex_wake_op(
+    addr1: &AtomicU32,
+    count1: u32,
+    addr2: &AtomicU32,
+    count2: u32,
+    encoded_op: u32,
+    flags: FutexFlags,
+) -> Result<usize, OsError> {
+    // The kernel takes count2 in place of the timeout pointer
+    let ret = unsafe {
+        syscall(
+            SYS_FUTEX,
+            addr1 as *const AtomicU32,
+            FutexOp::WakeOp.raw(flags),
+            count1,
+            count2 as usize as *const c_timespec,
+            addr2 as *const AtomicU32 as *const u32,
+            encoded_op,
+        )
+    };
+    check(ret).map(|woken| woken as usize)
+}
+
+// The ops moving waiters between words take the second word and the number
+// of waiters to move in place of the timeout pointer.
+unsafe fn futex_move(
+    from: &AtomicU32,
+    op: i32,
+    wake: u32,
+    to: &AtomicU32,
+    requeue: u32,
+    val3: u32,
+) -> Result<usize, OsError> {
+    let ret = syscall(
+        SYS_FUTEX,
+        from as *const AtomicU32,
+        op,
+        wake,
+        requeue as usize as *const c_timespec,
+        to as *const AtomicU32 as *const u32,
+        val3,
+    );
+    check(ret).map(|moved| moved as usize)
+}
+
+// Wakes up to wake waiters of from, and moves up to requeue of the others
+// over to wait on to, without waking them. A condvar can use it to wake a
+// single waiter on notify_all and queue the rest on the mutex, instead of
+// waking them all only for them to fight over the mutex.
+//
+// Returns the number of waiters woken plus the number requeued. Despite what
+// the man page says, the kernel counts both for FUTEX_REQUEUE too. Use
+// i32::MAX to requeue every waiter, the kernel reads the counts as signed
+// ints. If from and to are the same word, the waiters that are not woken
+// simply stay where they are.
+pub fn futex_requeue(
+    from: &AtomicU32,
+    to: &AtomicU32,
+    wake: u32,
+    requeue: u32,
+    flags: FutexFlags,
+) -> Result<usize, OsError> {
+    unsafe { futex_move(from, FutexOp::Requeue.raw(flags), wake, to, requeue, 0) }
+}
+
+// Like futex_requeue, but fails with WouldBlock if from no longer holds
+// expected, which closes the race between checking the word and moving its
+// waiters.
+pub fn futex_cmp_requeue(
+    from: &AtomicU32,
+    to: &AtomicU32,
+    wake: u32,
+    requeue: u32,
+    expected: u32,
+    flags: FutexFlags,
+) -> Result<usize, OsError> {
+    unsafe {
+        futex_move(
+            from,
+            FutexOp::CmpRequeue.raw(flags),
+            wake,
+            to,
+            requeue,
+            expected,
+        )
+    }
+}
+
+// The bits of a priority-inheritance futex word. The low bits hold the TID
+// of the thread owning the lock (see gettid), FUTEX_WAITERS is set by the
+// kernel while threads are blocked on it, and FUTEX_OWNER_DIED when the owner
+// exited without unlocking.
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+// Locks a priority-inheritance futex. Locking an unowned word can be done
+// from user space by swapping in our TID, this is needed once that fails, and
+// leaves the word with our TID once it returns successfully. Unlike
+// FUTEX_WAIT, the timeout of FUTEX_LOCK_PI is an absolute CLOCK_REALTIME
+// time.
+pub fn futex_lock_pi(
+    futex_addr: &AtomicU32,
+    timeout: Option<&FutexTimeout>,
+    flags: FutexFlags,
+) -> Result<i64, OsError> {
+    check(unsafe { futex(futex_addr, FutexOp::LockPi.raw(flags), 0, timeout, 0) })
+}
+
+// Unlocks a priority-inheritance futex owned by the calling thread, handing
+// it over to the highest priority waiter if there is one.
+pub fn futex_unlock_pi(futex_addr: &AtomicU32, flags: FutexFlags) -> Result<i64, OsError> {
+    check(unsafe { futex(futex_addr, FutexOp::UnlockPi.raw(flags), 0, None, 0) })
+}
+
+pub fn gettid() -> u32 {
+    unsafe { c_gettid() as u32 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+    #[cfg(not(feature = "std"))]
+    use std::vec::Vec;
+
+    #[test]
+    fn syscall_basic_functionality() {
+        let shared_int = AtomicU32::new(0);
+        let shared_int_addr = &shared_int as *const AtomicU32;
+        let res = unsafe { syscall(SYS_FUTEX, shared_int_addr, FUTEX_WAIT, 1, null(), null(), 0) };
+        assert_eq!(res, -1);
+    }
+
+    #[test]
+    fn futext_basic_functionality() {
+        let shared_int = AtomicU32::new(0);
+        let res = unsafe { futex(&shared_int, FUTEX_WAIT, 1, None, 0) };
+        assert_eq!(res, -1);
+
+        let res = unsafe { futex(&shared_int, FUTEX_WAKE, 1, None, 0) };
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn futex_op_raw_values() {
+        let ops = [
+            (FutexOp::Wait, 0),
+            (FutexOp::Wake, 1),
+            (FutexOp::Requeue, 3),
+            (FutexOp::CmpRequeue, 4),
+            (FutexOp::WakeOp, 5),
+            (FutexOp::LockPi, 6),
+            (FutexOp::UnlockPi, 7),
+            (FutexOp::WaitBitset, 9),
+            (FutexOp::WakeBitset, 10),
+        ];
+        for &(op, raw) in &ops {
+            assert_eq!(op.raw(FutexFlags::SHARED), raw);
+            assert_eq!(op.raw(FutexFlags::PRIVATE), raw | 128);
+        }
+    }
+
+    #[test]
+    fn futex_wake_after_wait() {
+        let shared_int = Arc::new(AtomicU32::new(0));
+        let shared_int2 = Arc::clone(&shared_int);
+
+        let handle = spawn(move || futex_wait(shared_int2.as_ref(), 0, None, FutexFlags::SHARED));
+
+        sleep(Duration::from_millis(2000));
+        let res = futex_wake(&shared_int, 1, None, FutexFlags::SHARED);
+        assert_eq!(res.unwrap(), 1);
+
+        // Checking that the return value is zero checks both that
+        // the thread was woken up, and with no errors
+        assert_eq!(handle.join().unwrap().unwrap(), 0);
+    }
+
+    #[test]
+    fn futex_private_wake_after_wait() {
+        let shared_int = Arc::new(AtomicU32::new(0));
+        let shared_int2 = Arc::clone(&shared_int);
+
+        let handle = spawn(move || futex_wait(&shared_int2, 0, None, FutexFlags::PRIVATE));
+
+        sleep(Duration::from_millis(500));
+        // A shared wake looks the word up differently and misses the waiter
+        assert_eq!(
+            futex_wake(&shared_int, 1, None, FutexFlags::SHARED).unwrap(),
+            0
+        );
+        assert_eq!(
+            futex_wake(&shared_int, 1, None, FutexFlags::PRIVATE).unwrap(),
+            1
+        );
+        assert_eq!(handle.join().unwrap().unwrap(), 0);
+    }
+
+    #[test]
+    fn futex_wake_bitset_only_wakes_matching_waiters() {
+        let shared_int = Arc::new(AtomicU32::new(0));
+        let woken = Arc::new(AtomicU32::new(0));
+
+        let handles: Vec<_> = [0b01, 0b10]
+            .iter()
+            .map(|&bitset| {
+                let shared_int = Arc::clone(&shared_int);
+                let woken = Arc::clone(&woken);
+                spawn(move || {
+                    let res = futex_wait_bitset(&shared_int, 0, bitset, None, FutexFlags::PRIVATE);
+                    woken.fetch_or(bitset, Ordering::SeqCst);
+                    res
+                })
+            })
+            .collect();
+
+        sleep(Duration::from_millis(500));
+        let res = futex_wake_bitset(&shared_int, i32::MAX as u32, 0b01, FutexFlags::PRIVATE);
+        assert_eq!(res.unwrap(), 1);
+        sleep(Duration::from_millis(100));
+        assert_eq!(woken.load(Ordering::SeqCst), 0b01);
+
+        let res = futex_wake_bitset(&shared_int, i32::MAX as u32, 0b10, FutexFlags::PRIVATE);
+        assert_eq!(res.unwrap(), 1);
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().unwrap(), 0);
+        }
+        assert_eq!(woken.load(Ordering::SeqCst), 0b11);
+    }
+
+    #[test]
+    fn futex_wake_op_sets_and_conditionally_wakes() {
+        let first = Arc::new(AtomicU32::new(0));
+        let second = Arc::new(AtomicU32::new(0));
+
+        let first2 = Arc::clone(&first);
+        let first_waiter = spawn(move || futex_wait(&first2, 0, None, FutexFlags::PRIVATE));
+        let second2 = Arc::clone(&second);
+        let second_waiter = spawn(move || futex_wait(&second2, 0, None, FutexFlags::PRIVATE));
+        sleep(Duration::from_millis(500));
+
+        // The old value is 0, so only the waiter of the first word wakes
+        let op = futex_wake_op_encode(FutexWakeOp::Set, 1, FutexWakeCmp::Eq, 5);
+        let res = futex_wake_op(&first, 1, &second, 1, op, FutexFlags::PRIVATE);
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(second.load(Ordering::SeqCst), 1);
+        assert_eq!(first_waiter.join().unwrap().unwrap(), 0);
+
+        let op = futex_wake_op_encode(FutexWakeOp::Add, 2, FutexWakeCmp::Eq, 1);
+        let res = futex_wake_op(&first, 1, &second, 1, op, FutexFlags::PRIVATE);
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(second.load(Ordering::SeqCst), 3);
+        assert_eq!(second_waiter.join().unwrap().unwrap(), 0);
+    }
+
+    #[test]
+    fn futex_requeue_wakes_one_and_moves_the_rest() {
+        const NUM_THREADS: usize = 3;
+
+        let condvar = Arc::new(AtomicU32::new(0));
+        let mutex = Arc::new(AtomicU32::new(0));
+        let running = Arc::new(AtomicU32::new(0));
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let condvar = Arc::clone(&condvar);
+                let running = Arc::clone(&running);
+                spawn(move || {
+                    let res = futex_wait(&condvar, 0, None, FutexFlags::PRIVATE);
+                    running.fetch_add(1, Ordering::SeqCst);
+                    res
+                })
+            })
+            .collect();
+        sleep(Duration::from_millis(500));
+
+        let err = futex_cmp_requeue(&condvar, &mutex, 1, i32::MAX as u32, 1, FutexFlags::PRIVATE)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+
+        let moved = futex_requeue(&condvar, &mutex, 1, i32::MAX as u32, FutexFlags::PRIVATE);
+        assert_eq!(moved.unwrap(), NUM_THREADS);
+        sleep(Duration::from_millis(200));
+        assert_eq!(running.load(Ordering::SeqCst), 1);
+        // The rest now wait on the mutex word
+        assert_eq!(
+            futex_wake(&condvar, i32::MAX as u32, None, FutexFlags::PRIVATE).unwrap(),
+            0
+        );
+        assert_eq!(
+            futex_wake(&mutex, i32::MAX as u32, None, FutexFlags::PRIVATE).unwrap(),
+            NUM_THREADS - 1
+        );
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().unwrap(), 0);
+        }
+    }
+
+    #[cfg(feature = "waitv")]
+    #[test]
+    fn futex_waitv_returns_the_woken_word() {
+        let first = Arc::new(AtomicU32::new(0));
+        let second = Arc::new(AtomicU32::new(0));
+
+        let first2 = Arc::clone(&first);
+        let second2 = Arc::clone(&second);
+        let handle = spawn(move || {
+            let entries = [
+                FutexWaitEntry::new(&first2, 0, FutexFlags::PRIVATE),
+                FutexWaitEntry::new(&second2, 0, FutexFlags::PRIVATE),
+            ];
+            futex_waitv(&entries, None)
+        });
+
+        sleep(Duration::from_millis(500));
+        assert_eq!(
+            futex_wake(&second, 1, None, FutexFlags::PRIVATE).unwrap(),
+            1
+        );
+        assert_eq!(handle.join().unwrap().unwrap(), 1);
+
+        let entries = [
+            FutexWaitEntry::new(&first, 0, FutexFlags::PRIVATE),
+            FutexWaitEntry::new(&second, 1, FutexFlags::PRIVATE),
+        ];
+        let err = futex_waitv(&entries, None).unwrap_err();
+        assert!(matches!(err, FutexError::WouldBlock));
+
+        let entries = [FutexWaitEntry::new(&first, 0, FutexFlags::PRIVATE)];
+        let timeout = FutexTimeout::from_millis(100);
+        let err = futex_waitv(&entries, Some(&timeout)).unwrap_err();
+        assert!(matches!(err, FutexError::TimedOut));
+    }
+
+    #[test]
+    fn futex_lock_pi_round_trip() {
+        let lock = AtomicU32::new(0);
+        let tid = gettid();
+
+        futex_lock_pi(&lock, None, FutexFlags::PRIVATE).unwrap();
+        assert_eq!(lock.load(Ordering::SeqCst) & FUTEX_TID_MASK, tid);
+        assert_eq!(lock.load(Ordering::SeqCst) & FUTEX_WAITERS, 0);
+
+        futex_unlock_pi(&lock, FutexFlags::PRIVATE).unwrap();
+        assert_eq!(lock.load(Ordering::SeqCst), 0);
+
+        // Unlocking a futex we do not own fails with EPERM
+        let err = futex_unlock_pi(&lock, FutexFlags::PRIVATE).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[test]
+    fn futex_wait_deadline_times_out() {
+        let shared_int = AtomicU32::new(0);
+
+        for &clock in &[ClockId::Monotonic, ClockId::Realtime] {
+            let start = std::time::Instant::now();
+            let now = clock_now(clock);
+            let deadline = FutexTimeout::new(now.0 + 1, now.1).unwrap();
+            let res = futex_wait_deadline(&shared_int, 0, &deadline, clock, FutexFlags::PRIVATE);
+            assert!(matches!(res, Err(FutexError::TimedOut)));
+            assert!(start.elapsed() >= Duration::from_millis(900));
+        }
+
+        // A deadline in the past expires right away
+        let start = std::time::Instant::now();
+        let deadline = FutexTimeout::new(0, 0).unwrap();
+        let res = futex_wait_deadline(
+            &shared_int,
+            0,
+            &deadline,
+            ClockId::Monotonic,
+            FutexFlags::PRIVATE,
+        );
+        assert!(matches!(res, Err(FutexError::TimedOut)));
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    extern "C" fn ignore_signal(_: libc::c_int) {}
+
+    #[test]
+    fn futex_wait_retries_when_interrupted() {
+        // SIGCONT does nothing by default, and the kernel would restart the
+        // wait by itself. With a handler to run it has to return EINTR.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore_signal as *const () as usize;
+            libc::sigaction(libc::SIGCONT, &action, std::ptr::null_mut());
+        }
+
+        let shared_int = Arc::new(AtomicU32::new(0));
+        let shared_int2 = Arc::clone(&shared_int);
+        let returned = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let returned2 = Arc::clone(&returned);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let handle = spawn(move || {
+            tx.send(unsafe { libc::pthread_self() }).unwrap();
+            let start = std::time::Instant::now();
+            let timeout = FutexTimeout::from_millis(1500);
+            let err = futex_wait(&shared_int2, 0, Some(&timeout), FutexFlags::PRIVATE).unwrap_err();
+            assert!(matches!(err, FutexError::TimedOut));
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(1500));
+            // Restarting with the whole timeout would take another second
+            assert!(elapsed < Duration::from_millis(2000));
+
+            let res = futex_wait(&shared_int2, 0, None, FutexFlags::PRIVATE);
+            returned2.store(true, Ordering::SeqCst);
+            res
+        });
+
+        let thread = rx.recv().unwrap();
+        for _ in 0..4 {
+            sleep(Duration::from_millis(250));
+            unsafe { libc::pthread_kill(thread, libc::SIGCONT) };
+        }
+        sleep(Duration::from_millis(1000));
+        unsafe { libc::pthread_kill(thread, libc::SIGCONT) };
+        sleep(Duration::from_millis(250));
+        assert!(!returned.load(Ordering::SeqCst));
+
+        futex_wake(&shared_int, 1, None, FutexFlags::PRIVATE).unwrap();
+        assert_eq!(handle.join().unwrap().unwrap(), 0);
+    }
+
+    #[test]
+    fn futex_wait_error_variants() {
+        let shared_int = AtomicU32::new(0);
+
+        let err = futex_wait(&shared_int, 1, None, FutexFlags::PRIVATE).unwrap_err();
+        assert!(matches!(err, FutexError::WouldBlock));
+
+        // The same timeout can be handed to any number of waits
+        let timeout = FutexTimeout(0, 10_000_000);
+        for _ in 0..3 {
+            let err = futex_wait(&shared_int, 0, Some(&timeout), FutexFlags::PRIVATE).unwrap_err();
+            assert!(matches!(err, FutexError::TimedOut));
+        }
+    }
+
+    #[test]
+    fn timespec_from_timeout_saturates() {
+        let timespec = c_timespec::from(&FutexTimeout::new(1, 500).unwrap());
+        assert_eq!((timespec.tv_sec, timespec.tv_nsec), (1, 500));
+
+        // Only clamps on 32-bit targets, where time_t cannot hold the seconds
+        let timespec = c_timespec::from(&FutexTimeout::new(i64::MAX, 0).unwrap());
+        assert_eq!(timespec.tv_sec, c_time_t::MAX);
+        let timespec = c_timespec::from(&FutexTimeout::new(i64::MIN, 0).unwrap());
+        assert_eq!(timespec.tv_sec, c_time_t::MIN);
+    }
+
+    #[test]
+    fn futex_wait_multi_second_timeout() {
+        let shared_int = AtomicU32::new(0);
+
+        let start = std::time::Instant::now();
+        let timeout = FutexTimeout::new(2, 0).unwrap();
+        let err = futex_wait(&shared_int, 0, Some(&timeout), FutexFlags::PRIVATE).unwrap_err();
+        assert!(matches!(err, FutexError::TimedOut));
+        // A tv_sec of the wrong width would garble the seconds and return
+        // way too early or fail with EINVAL
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(2));
+        assert!(elapsed < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn futex_wait_raw_timeout_out_of_range() {
+        let shared_int = AtomicU32::new(0);
+
+        let timeout = FutexTimeout::new_raw(0, 2_000_000_000);
+        let err = futex_wait(&shared_int, 0, Some(&timeout), FutexFlags::PRIVATE).unwrap_err();
+        match err {
+            FutexError::Other(err) => assert_eq!(err.raw_os_error(), Some(libc::EINVAL)),
+            err => panic!("expected EINVAL, got {:?}", err),
+        }
+    }
+
+    // Only run by timed_wait_prints_nothing, which looks at what it printed.
+    #[test]
+    #[ignore]
+    fn timed_wait_between_markers() {
+        let shared_int = AtomicU32::new(0);
+        let timeout = FutexTimeout::from_millis(100);
+
+        std::print!("<");
+        let start = std::time::Instant::now();
+        let err = futex_wait(&shared_int, 0, Some(&timeout), FutexFlags::PRIVATE).unwrap_err();
+        let elapsed = start.elapsed();
+        std::print!(">");
+        assert!(matches!(err, FutexError::TimedOut));
+        assert!(elapsed >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn timed_wait_prints_nothing() {
+        // The harness captures what tests print, so the wait runs in another
+        // instance of this binary, printing straight to its stdout.
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "linux::tests::timed_wait_between_markers",
+                "--exact",
+                "--ignored",
+                "--nocapture",
+            ])
+            .output()
+            .unwrap();
+        let stdout = std::str::from_utf8(&output.stdout).unwrap();
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("<>"), "{}", stdout);
+    }
+}
@@ -0,0 +1,138 @@
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::io;
+use std::sync::atomic::AtomicU32;
+use std::time::{Duration, Instant};
+
+use crate::{check, FutexError, FutexFlags, FutexTimeout};
+
+// macOS has no futex syscall, but libSystem exports the __ulock calls its
+// own locks are built on. They are private API, yet stable enough that most
+// runtimes rely on them.
+extern "C" {
+    fn __ulock_wait(operation: u32, addr: *mut c_void, value: u64, timeout_us: u32) -> i32;
+    fn __ulock_wake(operation: u32, addr: *mut c_void, wake_value: u64) -> i32;
+}
+
+const UL_COMPARE_AND_WAIT: i32 = 1;
+const UL_COMPARE_AND_WAIT_SHARED: i32 = 3;
+const ULF_WAKE_ALL: u32 = 0x100;
+
+const ENOENT: i32 = 2;
+
+pub(crate) const SHARED_FLAG: i32 = UL_COMPARE_AND_WAIT_SHARED;
+pub(crate) const PRIVATE_FLAG: i32 = UL_COMPARE_AND_WAIT;
+
+pub(crate) fn last_os_error() -> io::Error {
+    io::Error::last_os_error()
+}
+
+// __ulock_wait takes its timeout in microseconds, with zero meaning forever,
+// so expired or tiny timeouts are rounded up to a single microsecond.
+fn timeout_us(timeout: Option<Duration>) -> u32 {
+    match timeout {
+        None => 0,
+        Some(timeout) => {
+            let us = timeout.as_micros().max(1);
+            u32::try_from(us).unwrap_or(u32::MAX)
+        }
+    }
+}
+
+// The longest timeout a single __ulock_wait can take, about 71 minutes.
+const MAX_WAIT: Duration = Duration::from_micros(u32::MAX as u64);
+
+// Unlike on Linux, a word that does not hold val makes the wait return
+// successfully rather than fail with WouldBlock. Timeouts and signals still
+// fail with TimedOut and Interrupted. Timeouts longer than MAX_WAIT are
+// waited for in several rounds.
+pub fn futex_wait(
+    futex_addr: &AtomicU32,
+    val: u32,
+    timeout: Option<&FutexTimeout>,
+    flags: FutexFlags,
+) -> Result<i64, FutexError> {
+    let addr = futex_addr as *const AtomicU32 as *mut c_void;
+    if timeout.is_some_and(|timeout| !timeout.has_valid_nanos()) {
+        return Err(FutexError::Other(io::ErrorKind::InvalidInput.into()));
+    }
+    let timeout = timeout.map(FutexTimeout::as_duration);
+    // Only timeouts that do not fit a single wait need the clock
+    let start = match timeout {
+        Some(timeout) if timeout > MAX_WAIT => Some(Instant::now()),
+        _ => None,
+    };
+    loop {
+        let remaining = match (timeout, start) {
+            (Some(timeout), Some(start)) => Some(timeout.saturating_sub(start.elapsed())),
+            (timeout, _) => timeout,
+        };
+        let ret = unsafe { __ulock_wait(flags.0 as u32, addr, val as u64, timeout_us(remaining)) };
+        match check(ret as i64) {
+            // The wait was cut short to MAX_WAIT, there is more to go
+            Err(err) if err.kind() == io::ErrorKind::TimedOut && remaining > Some(MAX_WAIT) => {}
+            res => return Ok(res?),
+        }
+    }
+}
+
+// __ulock_wake can only wake one or all of the waiters, so any count above
+// one wakes all of them. It does not report how many threads it woke, so this
+// returns zero on success. The timeout is ignored, as on Linux.
+pub fn futex_wake(
+    futex_addr: &AtomicU32,
+    val: u32,
+    _timeout: Option<&FutexTimeout>,
+    flags: FutexFlags,
+) -> io::Result<usize> {
+    if val == 0 {
+        return Ok(0);
+    }
+    let mut operation = flags.0 as u32;
+    if val > 1 {
+        operation |= ULF_WAKE_ALL;
+    }
+    let addr = futex_addr as *const AtomicU32 as *mut c_void;
+    match check(unsafe { __ulock_wake(operation, addr, 0) } as i64) {
+        // Nobody was waiting
+        Err(err) if err.raw_os_error() == Some(ENOENT) => Ok(0),
+        Err(err) => Err(err),
+        Ok(_) => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+
+    #[test]
+    fn ulock_wake_after_wait() {
+        let shared_int = Arc::new(AtomicU32::new(0));
+        let woken = Arc::new(AtomicBool::new(false));
+        let shared_int2 = Arc::clone(&shared_int);
+        let woken2 = Arc::clone(&woken);
+
+        let handle = spawn(move || {
+            while !woken2.load(Ordering::SeqCst) {
+                futex_wait(&shared_int2, 0, None, FutexFlags::PRIVATE).unwrap();
+            }
+        });
+
+        sleep(Duration::from_millis(500));
+        woken.store(true, Ordering::SeqCst);
+        futex_wake(&shared_int, 1, None, FutexFlags::PRIVATE).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn ulock_timeout_rounds_up() {
+        assert_eq!(timeout_us(None), 0);
+        assert_eq!(timeout_us(Some(Duration::ZERO)), 1);
+        assert_eq!(timeout_us(Some(Duration::from_millis(1500))), 1_500_000);
+        assert_eq!(timeout_us(Some(MAX_WAIT)), u32::MAX);
+        assert_eq!(timeout_us(Some(Duration::MAX)), u32::MAX);
+    }
+}
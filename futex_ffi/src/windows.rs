@@ -0,0 +1,121 @@
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::io;
+use std::mem::size_of;
+use std::sync::atomic::AtomicU32;
+
+use crate::{FutexError, FutexFlags, FutexTimeout};
+
+// WaitOnAddress and friends from synchapi, exported by the
+// API-MS-Win-Core-Synch-l1-2-0 set that the synchronization import library
+// links against.
+#[link(name = "synchronization")]
+extern "system" {
+    fn WaitOnAddress(
+        address: *const c_void,
+        compare_address: *const c_void,
+        address_size: usize,
+        milliseconds: u32,
+    ) -> i32;
+    fn WakeByAddressSingle(address: *const c_void);
+    fn WakeByAddressAll(address: *const c_void);
+}
+
+const INFINITE: u32 = u32::MAX;
+
+// WaitOnAddress only works within a process, so both flags are the same.
+pub(crate) const SHARED_FLAG: i32 = 0;
+pub(crate) const PRIVATE_FLAG: i32 = 0;
+
+// Rounds up to whole milliseconds, so a wait never ends before the timeout.
+// INFINITE is reserved for waiting forever, so long timeouts stop just short
+// of it.
+fn timeout_ms(timeout: Option<&FutexTimeout>) -> u32 {
+    match timeout {
+        None => INFINITE,
+        Some(timeout) => {
+            let timeout = timeout.as_duration();
+            let partial_ms = timeout.subsec_nanos() % 1_000_000 != 0;
+            let ms = timeout.as_millis() + partial_ms as u128;
+            u32::try_from(ms).unwrap_or(INFINITE).min(INFINITE - 1)
+        }
+    }
+}
+
+// Unlike on Linux, a word that does not hold val makes the wait return
+// successfully rather than fail with WouldBlock. A timeout fails with
+// TimedOut.
+pub fn futex_wait(
+    futex_addr: &AtomicU32,
+    val: u32,
+    timeout: Option<&FutexTimeout>,
+    _flags: FutexFlags,
+) -> Result<i64, FutexError> {
+    // Refused like the kernel does on Linux
+    if timeout.is_some_and(|timeout| !timeout.has_valid_nanos()) {
+        return Err(FutexError::Other(io::ErrorKind::InvalidInput.into()));
+    }
+    let addr = futex_addr as *const AtomicU32 as *const c_void;
+    let compare = &val as *const u32 as *const c_void;
+    let ok = unsafe { WaitOnAddress(addr, compare, size_of::<u32>(), timeout_ms(timeout)) };
+    if ok == 0 {
+        Err(io::Error::last_os_error().into())
+    } else {
+        Ok(0)
+    }
+}
+
+// Windows can only wake one or all of the waiters, so any count above one
+// wakes all of them. It does not report how many threads it woke, so this
+// always returns zero. The timeout is ignored, as on Linux.
+pub fn futex_wake(
+    futex_addr: &AtomicU32,
+    val: u32,
+    _timeout: Option<&FutexTimeout>,
+    _flags: FutexFlags,
+) -> io::Result<usize> {
+    let addr = futex_addr as *const AtomicU32 as *const c_void;
+    match val {
+        0 => {}
+        1 => unsafe { WakeByAddressSingle(addr) },
+        _ => unsafe { WakeByAddressAll(addr) },
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn wait_on_address_woken_by_wake() {
+        let shared_int = Arc::new(AtomicU32::new(0));
+        let woken = Arc::new(AtomicBool::new(false));
+        let shared_int2 = Arc::clone(&shared_int);
+        let woken2 = Arc::clone(&woken);
+
+        let handle = spawn(move || {
+            while !woken2.load(Ordering::SeqCst) {
+                futex_wait(&shared_int2, 0, None, FutexFlags::PRIVATE).unwrap();
+            }
+        });
+
+        sleep(Duration::from_millis(500));
+        woken.store(true, Ordering::SeqCst);
+        futex_wake(&shared_int, 1, None, FutexFlags::PRIVATE).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn wait_on_address_timeout_in_ms() {
+        assert_eq!(timeout_ms(None), INFINITE);
+        assert_eq!(timeout_ms(Some(&FutexTimeout(0, 0))), 0);
+        assert_eq!(timeout_ms(Some(&FutexTimeout(0, 1))), 1);
+        assert_eq!(timeout_ms(Some(&FutexTimeout(1, 500_000_000))), 1500);
+        assert_eq!(timeout_ms(Some(&FutexTimeout(i64::MAX, 0))), INFINITE - 1);
+    }
+}